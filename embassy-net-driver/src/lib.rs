@@ -80,6 +80,40 @@ pub trait Driver {
     /// what kind of packet the sent/received bytes are, and determines some behaviors of
     /// the interface. For example, ARP/NDISC address resolution is only done for Ethernet mediums.
     fn hardware_address(&self) -> HardwareAddress;
+
+    /// Enable or disable promiscuous mode.
+    ///
+    /// In promiscuous mode the driver receives all frames, not only those addressed to it.
+    /// Drivers that can't do this return `Err(Unsupported)`, which is the default.
+    fn set_promiscuous(&mut self, enabled: bool) -> Result<(), Unsupported> {
+        let _ = enabled;
+        Err(Unsupported)
+    }
+
+    /// Enable or disable receiving all multicast frames.
+    ///
+    /// When enabled, the driver receives multicast frames for all groups, not only those it
+    /// has been configured to filter. Drivers that can't do this return `Err(Unsupported)`,
+    /// which is the default.
+    fn set_all_multicast(&mut self, enabled: bool) -> Result<(), Unsupported> {
+        let _ = enabled;
+        Err(Unsupported)
+    }
+
+    /// Get the frame counters of the driver.
+    ///
+    /// Drivers that don't keep counters return all zeros, which is the default.
+    fn stats(&self) -> Stats {
+        Stats::default()
+    }
+
+    /// Get the most recent transmit error of the driver, if any.
+    ///
+    /// Drivers that detect transmit failures, e.g. in their DMA or PHY error paths, can
+    /// report the last one here. The default returns `None`.
+    fn last_tx_error(&self) -> Option<TxError> {
+        None
+    }
 }
 
 impl<T: ?Sized + Driver> Driver for &mut T {
@@ -107,8 +141,57 @@ impl<T: ?Sized + Driver> Driver for &mut T {
     fn hardware_address(&self) -> HardwareAddress {
         T::hardware_address(self)
     }
+    fn set_promiscuous(&mut self, enabled: bool) -> Result<(), Unsupported> {
+        T::set_promiscuous(self, enabled)
+    }
+    fn set_all_multicast(&mut self, enabled: bool) -> Result<(), Unsupported> {
+        T::set_all_multicast(self, enabled)
+    }
+    fn stats(&self) -> Stats {
+        T::stats(self)
+    }
+    fn last_tx_error(&self) -> Option<TxError> {
+        T::last_tx_error(self)
+    }
+}
+
+/// Frame counters of a driver.
+///
+/// All counters wrap around on overflow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Stats {
+    /// Number of frames transmitted.
+    pub tx_frames: u32,
+    /// Number of frames received and passed up to the stack.
+    pub rx_frames: u32,
+    /// Number of frames that could not be transmitted, e.g. because the transmit queue was full.
+    pub tx_dropped: u32,
+    /// Number of received frames dropped by the driver, e.g. because no receive buffer was free.
+    pub rx_dropped: u32,
+}
+
+/// Transmit error reported by a driver, see [`Driver::last_tx_error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum TxError {
+    /// The DMA engine reported an error.
+    Dma,
+    /// The PHY or link reported an error, e.g. carrier loss or a collision.
+    Phy,
+    /// The hardware did not complete the transmission in time.
+    Timeout,
+    /// Driver-specific error code.
+    Other(u32),
 }
 
+/// Error returned when a driver doesn't support the requested operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Unsupported;
+
 /// A token to receive a single network packet.
 pub trait RxToken {
     /// Consumes the token to receive a single network packet.
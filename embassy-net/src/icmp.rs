@@ -378,7 +378,10 @@ pub mod ping {
     //!
     //! ## Usage
     //!
-    //! ```
+    //! ```no_run
+    //! # macro_rules! info { ($($t:tt)*) => {{}}; }
+    //! # macro_rules! warn { ($($t:tt)*) => {{}}; }
+    //! # async fn example(stack: embassy_net::Stack<'_>) {
     //! use core::net::Ipv4Addr;
     //! use core::str::FromStr;
     //!
@@ -398,6 +401,7 @@ pub mod ping {
     //!     Ok(time) => info!("Ping time of {}: {}ms", addr, time.as_millis()),
     //!     Err(ping_error) => warn!("{:?}", ping_error),
     //! };
+    //! # }
     //! ```
 
     use core::net::IpAddr;
@@ -17,6 +17,10 @@ pub mod dns;
 mod driver_util;
 #[cfg(feature = "icmp")]
 pub mod icmp;
+#[cfg(feature = "test-util")]
+pub mod loopback;
+#[cfg(any(feature = "tcp", feature = "udp"))]
+mod pool;
 #[cfg(feature = "raw")]
 pub mod raw;
 #[cfg(feature = "tcp")]
@@ -33,19 +37,25 @@ use core::task::{Context, Poll};
 
 pub use embassy_net_driver as driver;
 use embassy_net_driver::{Driver, LinkState};
-use embassy_sync::waitqueue::WakerRegistration;
-use embassy_time::{Instant, Timer};
+use embassy_sync::waitqueue::{MultiWakerRegistration, WakerRegistration};
+use embassy_time::{Duration, Instant, Timer};
+#[cfg(feature = "multicast")]
+use heapless::LinearMap;
 use heapless::Vec;
 #[cfg(feature = "dns")]
 pub use smoltcp::config::DNS_MAX_SERVER_COUNT;
 #[cfg(feature = "multicast")]
+use smoltcp::config::IFACE_MAX_MULTICAST_GROUP_COUNT;
+#[cfg(feature = "multicast")]
 pub use smoltcp::iface::MulticastError;
 #[cfg(any(feature = "dns", feature = "dhcpv4"))]
 use smoltcp::iface::SocketHandle;
 use smoltcp::iface::{Interface, SocketSet, SocketStorage};
-use smoltcp::phy::Medium;
+use smoltcp::phy::{Device as _, Medium};
 #[cfg(feature = "dhcpv4")]
 use smoltcp::socket::dhcpv4::{self, RetryConfig};
+#[cfg(any(feature = "udp", feature = "tcp"))]
+use smoltcp::socket::Socket;
 #[cfg(feature = "medium-ethernet")]
 pub use smoltcp::wire::EthernetAddress;
 #[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154", feature = "medium-ip"))]
@@ -242,6 +252,11 @@ pub enum ConfigV6 {
     Static(StaticConfigV6),
 }
 
+/// Error returned by [`Stack::set_ephemeral_port_range`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InvalidPortRange;
+
 /// Network stack runner.
 ///
 /// You must call [`Runner::run()`] in a background task for the network stack to work.
@@ -259,15 +274,60 @@ pub struct Stack<'d> {
     inner: &'d RefCell<Inner>,
 }
 
+/// A driver mode change requested through the [`Stack`], applied by the [`Runner`].
+struct ModeRequest {
+    pending: Option<bool>,
+    result: Option<Result<(), driver::Unsupported>>,
+}
+
+impl ModeRequest {
+    const fn new() -> Self {
+        Self {
+            pending: None,
+            result: None,
+        }
+    }
+
+    fn apply(&mut self, f: impl FnOnce(bool) -> Result<(), driver::Unsupported>) -> bool {
+        let Some(enabled) = self.pending.take() else {
+            return false;
+        };
+        self.result = Some(f(enabled));
+        true
+    }
+}
+
 pub(crate) struct Inner {
     pub(crate) sockets: SocketSet<'static>, // Lifetime type-erased.
+    socket_capacity: usize,
     pub(crate) iface: Interface,
     /// Waker used for triggering polls.
     pub(crate) waker: WakerRegistration,
     /// Waker used for waiting for link up or config up.
     state_waker: WakerRegistration,
+    /// Incremented every time a configuration is applied.
+    config_generation: u32,
+    /// Incremented every time the interface's IP addresses change.
+    pub(crate) addr_generation: u32,
+    /// Wakers used for waiting for an address change.
+    pub(crate) addr_waker: MultiWakerRegistration<4>,
     hardware_address: HardwareAddress,
+    ip_mtu: usize,
+    promiscuous: ModeRequest,
+    all_multicast: ModeRequest,
+    /// Wakers used for waiting for a driver mode change to be applied.
+    mode_waker: MultiWakerRegistration<4>,
+    device_stats: driver::Stats,
+    last_device_error: Option<driver::TxError>,
+    stop_requested: bool,
+    pub(crate) stopped: bool,
+    #[cfg(feature = "congestion-hook")]
+    pub(crate) congestion_hook: Option<fn(udp::CongestionEvent)>,
+    #[cfg(feature = "multicast")]
+    multicast_refs: LinearMap<IpAddress, u16, IFACE_MAX_MULTICAST_GROUP_COUNT>,
     next_local_port: u16,
+    local_port_min: u16,
+    local_port_max: u16,
     link_up: bool,
     #[cfg(feature = "proto-ipv4")]
     static_v4: Option<StaticConfigV4>,
@@ -298,15 +358,13 @@ pub fn new<'d, D: Driver, const SOCK: usize>(
     let mut iface_cfg = smoltcp::iface::Config::new(hardware_address);
     iface_cfg.random_seed = random_seed;
 
-    let iface = Interface::new(
-        iface_cfg,
-        &mut DriverAdapter {
-            inner: &mut driver,
-            cx: None,
-            medium,
-        },
-        instant_to_smoltcp(Instant::now()),
-    );
+    let mut adapter = DriverAdapter {
+        inner: &mut driver,
+        cx: None,
+        medium,
+    };
+    let ip_mtu = adapter.capabilities().ip_mtu();
+    let iface = Interface::new(iface_cfg, &mut adapter, instant_to_smoltcp(Instant::now()));
 
     unsafe fn transmute_slice<T>(x: &mut [T]) -> &'static mut [T] {
         core::mem::transmute(x)
@@ -328,11 +386,29 @@ pub fn new<'d, D: Driver, const SOCK: usize>(
 
     let mut inner = Inner {
         sockets,
+        socket_capacity: SOCK,
         iface,
         waker: WakerRegistration::new(),
         state_waker: WakerRegistration::new(),
+        config_generation: 0,
+        addr_generation: 0,
+        addr_waker: MultiWakerRegistration::new(),
         next_local_port,
+        local_port_min: LOCAL_PORT_MIN,
+        local_port_max: LOCAL_PORT_MAX,
         hardware_address,
+        ip_mtu,
+        promiscuous: ModeRequest::new(),
+        all_multicast: ModeRequest::new(),
+        mode_waker: MultiWakerRegistration::new(),
+        #[cfg(feature = "multicast")]
+        multicast_refs: LinearMap::new(),
+        device_stats: driver::Stats::default(),
+        last_device_error: None,
+        stop_requested: false,
+        stopped: false,
+        #[cfg(feature = "congestion-hook")]
+        congestion_hook: None,
         link_up: false,
         #[cfg(feature = "proto-ipv4")]
         static_v4: None,
@@ -393,6 +469,235 @@ impl<'d> Stack<'d> {
         self.with(|i| i.hardware_address)
     }
 
+    /// Get the IP MTU of the network interface.
+    ///
+    /// This is the largest IP packet the driver can send in a single frame, i.e. the
+    /// driver's MTU without the link-layer header, as computed by smoltcp.
+    ///
+    /// On IEEE 802.15.4, smoltcp reports the driver's frame MTU as is. The actual limit depends
+    /// on the 6LoWPAN header compression and fragmentation applied to each packet.
+    pub fn ip_mtu(&self) -> usize {
+        self.with(|i| i.ip_mtu)
+    }
+
+    /// Get the largest UDP payload that can be sent to `remote` without IP fragmentation.
+    ///
+    /// This is [`ip_mtu`](Self::ip_mtu) minus the IP and UDP header sizes, which differ
+    /// between IPv4 and IPv6. IP options and IPv6 extension headers are not taken into account.
+    #[cfg(feature = "udp")]
+    pub fn udp_payload_mtu(&self, remote: IpAddress) -> usize {
+        let ip_header_len = match remote {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(_) => smoltcp::wire::IPV4_HEADER_LEN,
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(_) => smoltcp::wire::IPV6_HEADER_LEN,
+        };
+        self.ip_mtu()
+            .saturating_sub(ip_header_len + smoltcp::wire::UDP_HEADER_LEN)
+    }
+
+    /// Get the number of sockets the stack can hold, i.e. the `SOCK` parameter of its
+    /// [`StackResources`].
+    pub fn socket_capacity(&self) -> usize {
+        self.with(|i| i.socket_capacity)
+    }
+
+    /// Get the number of sockets currently allocated.
+    ///
+    /// This includes the sockets the stack uses internally for DNS and DHCP.
+    pub fn socket_count(&self) -> usize {
+        self.with(|i| i.socket_count())
+    }
+
+    /// Get the frame counters of the driver.
+    ///
+    /// The counters are read from [`Driver::stats()`](driver::Driver::stats) by the
+    /// [`Runner`] on every poll, so they may lag slightly behind the driver.
+    pub fn device_stats(&self) -> driver::Stats {
+        self.with(|i| i.device_stats)
+    }
+
+    /// Get the most recent transmit error reported by the driver, if any.
+    ///
+    /// Like [`device_stats`](Self::device_stats), this is read from
+    /// [`Driver::last_tx_error()`](driver::Driver::last_tx_error) on every poll. It helps
+    /// finding out why sends stall when the driver keeps failing to transmit.
+    pub fn last_device_error(&self) -> Option<driver::TxError> {
+        self.with(|i| i.last_device_error)
+    }
+
+    /// Set a hook called on socket congestion events, or remove it with `None`.
+    ///
+    /// The hook is called when a UDP socket's send has to wait for transmit buffer space, or
+    /// when a socket discards a received datagram, so applications can throttle producers.
+    /// It runs in the task using the socket, after the stack has been released, so it may
+    /// use the stack, but it should return quickly.
+    ///
+    /// Datagrams dropped because a socket's receive buffer was full are not reported, smoltcp
+    /// discards them without notice. Receive buffer congestion only shows up as missing data.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4", feature = "congestion-hook"))]
+    /// # {
+    /// use core::future::Future;
+    /// use core::pin::pin;
+    /// use core::sync::atomic::{AtomicU32, Ordering};
+    /// use core::task::{Context, Poll, Waker};
+    ///
+    /// use embassy_futures::block_on;
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{CongestionEvent, PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// static TX_BLOCKED: AtomicU32 = AtomicU32::new(0);
+    ///
+    /// fn hook(event: CongestionEvent) {
+    ///     if let CongestionEvent::TxBlocked { local_port: 1234 } = event {
+    ///         TX_BLOCKED.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// }
+    ///
+    /// let config = Config::ipv4_static(StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway: None,
+    ///     dns_servers: Default::default(),
+    /// });
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<2>::new();
+    /// // The runner is never run, so queued datagrams are never sent.
+    /// let (stack, _runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+    /// stack.set_congestion_hook(Some(hook));
+    ///
+    /// let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    /// let mut rx = [0; 64];
+    /// let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    /// let mut tx = [0; 64];
+    /// let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+    /// socket.bind(1234).unwrap();
+    ///
+    /// let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    /// block_on(socket.send_to(b"first", remote)).unwrap();
+    /// assert_eq!(TX_BLOCKED.load(Ordering::Relaxed), 0);
+    ///
+    /// // The transmit buffer is full now, so the next send has to wait.
+    /// let mut send = pin!(socket.send_to(b"second", remote));
+    /// let mut cx = Context::from_waker(Waker::noop());
+    /// assert!(send.as_mut().poll(&mut cx).is_pending());
+    /// assert_eq!(TX_BLOCKED.load(Ordering::Relaxed), 1);
+    /// # }
+    /// ```
+    #[cfg(feature = "congestion-hook")]
+    pub fn set_congestion_hook(&self, hook: Option<fn(udp::CongestionEvent)>) {
+        self.with_mut(|i| i.congestion_hook = hook)
+    }
+
+    /// Enable or disable promiscuous mode on the driver.
+    ///
+    /// The change is applied by the [`Runner`] on its next poll, this waits until it has been.
+    /// Returns `Err(Unsupported)` if the driver doesn't support promiscuous mode, in which
+    /// case the mode is left unchanged. If several tasks change the mode at the same time, the
+    /// last change wins and all of them get its result.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use core::cell::Cell;
+    /// use core::task::Context;
+    ///
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select, Either};
+    /// use embassy_net::driver::{Capabilities, Driver, HardwareAddress, LinkState, Unsupported};
+    /// use embassy_net::loopback::{LoopbackDevice, LoopbackState};
+    /// use embassy_net::{Config, StackResources};
+    ///
+    /// /// Supports promiscuous mode, but not receiving all multicast frames.
+    /// struct MockDriver<'d> {
+    ///     inner: LoopbackDevice<'d, 1500, 4>,
+    ///     promiscuous: &'d Cell<bool>,
+    /// }
+    ///
+    /// impl<'d> Driver for MockDriver<'d> {
+    ///     type RxToken<'a> = <LoopbackDevice<'d, 1500, 4> as Driver>::RxToken<'a> where Self: 'a;
+    ///     type TxToken<'a> = <LoopbackDevice<'d, 1500, 4> as Driver>::TxToken<'a> where Self: 'a;
+    ///
+    ///     fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+    ///         self.inner.receive(cx)
+    ///     }
+    ///     fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+    ///         self.inner.transmit(cx)
+    ///     }
+    ///     fn link_state(&mut self, cx: &mut Context) -> LinkState {
+    ///         self.inner.link_state(cx)
+    ///     }
+    ///     fn capabilities(&self) -> Capabilities {
+    ///         self.inner.capabilities()
+    ///     }
+    ///     fn hardware_address(&self) -> HardwareAddress {
+    ///         self.inner.hardware_address()
+    ///     }
+    ///     fn set_promiscuous(&mut self, enabled: bool) -> Result<(), Unsupported> {
+    ///         self.promiscuous.set(enabled);
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let promiscuous = Cell::new(false);
+    /// let driver = MockDriver {
+    ///     inner: state.device(),
+    ///     promiscuous: &promiscuous,
+    /// };
+    /// let mut resources = StackResources::<2>::new();
+    /// let (stack, mut runner) = embassy_net::new(driver, Config::default(), &mut resources, 1);
+    ///
+    /// let test = async {
+    ///     assert_eq!(stack.set_promiscuous(true).await, Ok(()));
+    ///     assert!(promiscuous.get());
+    ///     assert_eq!(stack.set_all_multicast(true).await, Err(Unsupported));
+    ///     assert_eq!(stack.set_promiscuous(false).await, Ok(()));
+    ///     assert!(!promiscuous.get());
+    /// };
+    /// match block_on(select(test, runner.run())) {
+    ///     Either::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub async fn set_promiscuous(&self, enabled: bool) -> Result<(), driver::Unsupported> {
+        self.set_mode(|i| &mut i.promiscuous, enabled).await
+    }
+
+    /// Enable or disable receiving all multicast frames on the driver.
+    ///
+    /// Like [`set_promiscuous`](Self::set_promiscuous), this waits until the [`Runner`] has
+    /// applied the change, and returns `Err(Unsupported)` if the driver doesn't support it.
+    pub async fn set_all_multicast(&self, enabled: bool) -> Result<(), driver::Unsupported> {
+        self.set_mode(|i| &mut i.all_multicast, enabled).await
+    }
+
+    async fn set_mode(
+        &self,
+        mode: fn(&mut Inner) -> &mut ModeRequest,
+        enabled: bool,
+    ) -> Result<(), driver::Unsupported> {
+        self.with_mut(|i| {
+            let mode = mode(i);
+            mode.pending = Some(enabled);
+            mode.result = None;
+            i.waker.wake();
+        });
+        poll_fn(|cx| {
+            self.with_mut(|i| match mode(i).result {
+                Some(result) => Poll::Ready(result),
+                None => {
+                    i.mode_waker.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
     /// Check whether the link is up.
     pub fn is_link_up(&self) -> bool {
         self.with(|i| i.link_up)
@@ -491,6 +796,43 @@ impl<'d> Stack<'d> {
         })
     }
 
+    /// Get the configuration generation.
+    ///
+    /// This counter is incremented, wrapping around on overflow, every time an IP configuration
+    /// is applied: when one is set with [`set_config_v4`](Self::set_config_v4) or
+    /// [`set_config_v6`](Self::set_config_v6), and when DHCP obtains, changes or loses a
+    /// lease. This includes changes of the addresses, gateways and DNS servers. Caches derived
+    /// from the configuration can store the generation, and compare it to know when to refresh.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::{Config, ConfigV4, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// let config = StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway: None,
+    ///     dns_servers: Default::default(),
+    /// };
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<2>::new();
+    /// let (stack, _runner) = embassy_net::new(state.device(), Config::ipv4_static(config.clone()), &mut resources, 1);
+    ///
+    /// let generation = stack.config_generation();
+    /// stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+    ///     gateway: Some(Ipv4Address::new(10, 0, 0, 254)),
+    ///     ..config
+    /// }));
+    /// assert_eq!(stack.config_generation(), generation.wrapping_add(1));
+    /// stack.set_config_v4(ConfigV4::None);
+    /// assert_eq!(stack.config_generation(), generation.wrapping_add(2));
+    /// # }
+    /// ```
+    pub fn config_generation(&self) -> u32 {
+        self.with(|i| i.config_generation)
+    }
+
     /// Get the current IPv4 configuration.
     ///
     /// If using DHCP, this will be None if DHCP hasn't been able to
@@ -524,6 +866,198 @@ impl<'d> Stack<'d> {
         })
     }
 
+    /// Set the range dynamic local ports are allocated from.
+    ///
+    /// This is used when binding a socket to port 0 or connecting a TCP socket.
+    /// Both ends are inclusive, and the default is `1025..=65535`.
+    ///
+    /// Returns `Err(InvalidPortRange)` if `start` is 0 or greater than `end`.
+    pub fn set_ephemeral_port_range(&self, start: u16, end: u16) -> Result<(), InvalidPortRange> {
+        if start == 0 || start > end {
+            return Err(InvalidPortRange);
+        }
+        self.with_mut(|i| {
+            i.local_port_min = start;
+            i.local_port_max = end;
+            if !(start..=end).contains(&i.next_local_port) {
+                i.next_local_port = start;
+            }
+        });
+        Ok(())
+    }
+
+    /// Reseed the dynamic local port allocator.
+    ///
+    /// Ports are handed out sequentially within the range set by
+    /// [`set_ephemeral_port_range`](Self::set_ephemeral_port_range), starting from a port
+    /// derived from the random seed passed to [`new`]. After this call the sequence restarts
+    /// from a port derived from `seed` instead, so the same seed always yields the same
+    /// sequence of ports for the same range.
+    pub fn set_ephemeral_port_seed(&self, seed: u16) {
+        self.with_mut(|i| {
+            let len = u32::from(i.local_port_max - i.local_port_min) + 1;
+            i.next_local_port = i.local_port_min + (u32::from(seed) % len) as u16;
+        })
+    }
+
+    /// Allocate a dynamic local port.
+    ///
+    /// Returns the next port from the range set by
+    /// [`set_ephemeral_port_range`](Self::set_ephemeral_port_range) that no socket uses, or
+    /// `None` if all of them are in use. The port is not reserved: until a socket is actually
+    /// bound to it, the stack may hand the same port out again.
+    pub fn alloc_ephemeral_port(&self) -> Option<u16> {
+        self.with_mut(|i| i.get_local_port())
+    }
+
+    /// Check whether there is a route to `addr`.
+    ///
+    /// Returns `true` if `addr` is in the subnet of one of the interface addresses, is a
+    /// broadcast or multicast address, or if a default gateway is configured for its address
+    /// family. This does not check whether the host is actually reachable.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::{Config, ConfigV4, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// let static_config = |gateway| StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway,
+    ///     dns_servers: Default::default(),
+    /// };
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<2>::new();
+    /// let config = Config::ipv4_static(static_config(Some(Ipv4Address::new(10, 0, 0, 254))));
+    /// let (stack, _runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+    ///
+    /// let remote = Ipv4Address::new(192, 168, 1, 1).into();
+    /// assert!(stack.has_route_to(remote));
+    ///
+    /// // Without a gateway, only the local subnet is routable.
+    /// stack.set_config_v4(ConfigV4::Static(static_config(None)));
+    /// assert!(!stack.has_route_to(remote));
+    /// assert!(stack.has_route_to(Ipv4Address::new(10, 0, 0, 2).into()));
+    /// # }
+    /// ```
+    pub fn has_route_to(&self, addr: IpAddress) -> bool {
+        self.with(|i| {
+            if addr.is_broadcast() || addr.is_multicast() {
+                return true;
+            }
+            if i.iface.ip_addrs().iter().any(|cidr| cidr.contains_addr(&addr)) {
+                return true;
+            }
+
+            // smoltcp doesn't allow reading the route table without borrowing it mutably, but
+            // the only routes in there are the default gateways of the current configuration.
+            match addr {
+                #[cfg(feature = "proto-ipv4")]
+                IpAddress::Ipv4(_) => i.static_v4.as_ref().is_some_and(|c| c.gateway.is_some()),
+                #[cfg(feature = "proto-ipv6")]
+                IpAddress::Ipv6(_) => i.static_v6.as_ref().is_some_and(|c| c.gateway.is_some()),
+            }
+        })
+    }
+
+    /// Request the stack to stop.
+    ///
+    /// This makes [`Runner::run_until_stopped`] return once queued UDP datagrams have been sent,
+    /// or its drain timeout has passed. [`Runner::run`] ignores and discards stop requests.
+    pub fn request_stop(&self) {
+        self.with_mut(|i| {
+            i.stop_requested = true;
+            i.waker.wake();
+        })
+    }
+
+    /// Clear the neighbor (ARP/NDP) cache.
+    ///
+    /// The hardware addresses of all neighbors are forgotten, so the next packet sent to each of
+    /// them triggers address resolution again, e.g. after a peer was replaced by another device
+    /// with the same IP address.
+    ///
+    /// smoltcp doesn't expose the entries of the cache, so whether a neighbor is currently
+    /// resolved can't be queried.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4", feature = "medium-ethernet"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1514, 4>::new();
+    /// let (dev_a, dev_b) = state.pair_ethernet();
+    ///
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    /// // The Ethernet header doesn't count towards the IP MTU.
+    /// assert_eq!(stack_a.ip_mtu(), 1500);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 1024];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 1024];
+    /// let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// a.bind(1234).unwrap();
+    ///
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 1024];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 1024];
+    /// let mut b = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// b.bind(5678).unwrap();
+    ///
+    /// let test = async {
+    ///     let mut buf = [0; 16];
+    ///     let peer = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    ///
+    ///     // The first datagram waits for an ARP request and its reply.
+    ///     a.send_to(b"one", peer).await.unwrap();
+    ///     b.recv_from(&mut buf).await.unwrap();
+    ///     assert_eq!(stack_a.device_stats().tx_frames, 2);
+    ///
+    ///     // The peer's address is cached now.
+    ///     a.send_to(b"two", peer).await.unwrap();
+    ///     b.recv_from(&mut buf).await.unwrap();
+    ///     assert_eq!(stack_a.device_stats().tx_frames, 3);
+    ///
+    ///     // After flushing, the peer is resolved again.
+    ///     stack_a.flush_neighbor_cache();
+    ///     a.send_to(b"three", peer).await.unwrap();
+    ///     let (n, _) = b.recv_from(&mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..n], b"three");
+    ///     assert_eq!(stack_a.device_stats().tx_frames, 5);
+    /// };
+    ///
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub fn flush_neighbor_cache(&self) {
+        self.with_mut(|i| {
+            // smoltcp only exposes flushing the cache as part of updating the addresses.
+            i.iface.update_ip_addrs(|_| {});
+            i.waker.wake();
+        })
+    }
+
     /// Make a query for a given name and return the corresponding IP addresses.
     #[cfg(feature = "dns")]
     pub async fn dns_query(
@@ -628,13 +1162,22 @@ impl<'d> Stack<'d> {
 #[cfg(feature = "multicast")]
 impl<'d> Stack<'d> {
     /// Join a multicast group.
+    ///
+    /// Memberships are reference-counted: joining a group that is already joined, from here or
+    /// from a socket, only increments its count, and the interface leaves the group once each
+    /// join has been matched by a [`leave_multicast_group`](Self::leave_multicast_group).
     pub fn join_multicast_group(&self, addr: impl Into<IpAddress>) -> Result<(), MulticastError> {
-        self.with_mut(|i| i.iface.join_multicast_group(addr))
+        self.with_mut(|i| i.join_multicast_group(addr.into()))?;
+        Ok(())
     }
 
     /// Leave a multicast group.
+    ///
+    /// The interface only leaves the group when this drops the last reference to it, see
+    /// [`join_multicast_group`](Self::join_multicast_group).
     pub fn leave_multicast_group(&self, addr: impl Into<IpAddress>) -> Result<(), MulticastError> {
-        self.with_mut(|i| i.iface.leave_multicast_group(addr))
+        self.with_mut(|i| i.leave_multicast_group(addr.into()))?;
+        Ok(())
     }
 
     /// Get whether the network stack has joined the given multicast group.
@@ -644,11 +1187,123 @@ impl<'d> Stack<'d> {
 }
 
 impl Inner {
-    #[allow(clippy::absurd_extreme_comparisons)]
-    pub fn get_local_port(&mut self) -> u16 {
-        let res = self.next_local_port;
-        self.next_local_port = if res >= LOCAL_PORT_MAX { LOCAL_PORT_MIN } else { res + 1 };
-        res
+    /// Add a reference to a multicast group, joining it on the interface if it's the first one.
+    ///
+    /// Returns whether the interface joined the group.
+    #[cfg(feature = "multicast")]
+    pub(crate) fn join_multicast_group(&mut self, addr: IpAddress) -> Result<bool, MulticastError> {
+        if let Some(refs) = self.multicast_refs.get_mut(&addr) {
+            *refs = refs.saturating_add(1);
+            return Ok(false);
+        }
+
+        self.iface.join_multicast_group(addr)?;
+        if self.multicast_refs.insert(addr, 1).is_err() {
+            // Groups the interface already knew about, but we didn't, filled up the table.
+            let _ = self.iface.leave_multicast_group(addr);
+            return Err(MulticastError::GroupTableFull);
+        }
+        self.waker.wake();
+        Ok(true)
+    }
+
+    /// Drop a reference to a multicast group, leaving it on the interface if it was the last one.
+    ///
+    /// Returns whether the interface left the group.
+    #[cfg(feature = "multicast")]
+    pub(crate) fn leave_multicast_group(&mut self, addr: IpAddress) -> Result<bool, MulticastError> {
+        match self.multicast_refs.get_mut(&addr) {
+            Some(refs) if *refs > 1 => {
+                *refs -= 1;
+                Ok(false)
+            }
+            Some(_) => {
+                self.iface.leave_multicast_group(addr)?;
+                self.multicast_refs.remove(&addr);
+                self.waker.wake();
+                Ok(true)
+            }
+            None => {
+                self.iface.leave_multicast_group(addr)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Stop the stack, failing pending and new UDP socket operations until it runs again.
+    fn stop(&mut self) {
+        self.stop_requested = false;
+        self.stopped = true;
+        for (_, socket) in self.sockets.iter_mut() {
+            match socket {
+                #[cfg(feature = "udp")]
+                Socket::Udp(s) => {
+                    // Closing the socket is the only way to wake the tasks waiting on it. Bind it
+                    // again right away, so it keeps its endpoint when the stack runs again.
+                    let endpoint = s.endpoint();
+                    s.close();
+                    if endpoint.port != 0 {
+                        let _ = s.bind(endpoint);
+                    }
+                }
+                // The connection can't survive the stack being stopped. The reset is sent if the
+                // stack runs again.
+                #[cfg(feature = "tcp")]
+                Socket::Tcp(s) => s.abort(),
+                #[allow(unreachable_patterns)]
+                _ => {}
+            }
+        }
+    }
+
+    fn udp_tx_idle(&self) -> bool {
+        self.sockets.iter().all(|(_, socket)| match socket {
+            #[cfg(feature = "udp")]
+            Socket::Udp(s) => s.send_queue() == 0,
+            #[allow(unreachable_patterns)]
+            _ => true,
+        })
+    }
+
+    pub(crate) fn socket_count(&self) -> usize {
+        self.sockets.iter().count()
+    }
+
+    #[allow(unused)] // unused depending on which sockets are enabled
+    pub(crate) fn has_free_socket(&self) -> bool {
+        self.socket_count() < self.socket_capacity
+    }
+
+    /// Get the next dynamic local port not used by any socket.
+    pub fn get_local_port(&mut self) -> Option<u16> {
+        // Each socket uses at most one port, so one more try than there are sockets always
+        // finds a free port, unless the dynamic range is smaller than that.
+        let len = u32::from(self.local_port_max - self.local_port_min) + 1;
+        let tries = len.min(self.socket_count() as u32 + 1);
+        for _ in 0..tries {
+            let res = self.next_local_port;
+            self.next_local_port = if res >= self.local_port_max {
+                self.local_port_min
+            } else {
+                res + 1
+            };
+            if !self.port_in_use(res) {
+                return Some(res);
+            }
+        }
+        None
+    }
+
+    #[allow(unused_variables)] // unused depending on which sockets are enabled
+    fn port_in_use(&self, port: u16) -> bool {
+        self.sockets.iter().any(|(_, socket)| match socket {
+            #[cfg(feature = "udp")]
+            Socket::Udp(s) => s.endpoint().port == port,
+            #[cfg(feature = "tcp")]
+            Socket::Tcp(s) => s.listen_endpoint().port == port || s.local_endpoint().is_some_and(|e| e.port == port),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        })
     }
 
     #[cfg(feature = "proto-ipv4")]
@@ -762,6 +1417,10 @@ impl Inner {
         }
 
         // Apply addresses
+        if self.iface.ip_addrs() != &addrs[..] {
+            self.addr_generation = self.addr_generation.wrapping_add(1);
+            self.addr_waker.wake();
+        }
         self.iface.update_ip_addrs(|a| *a = addrs);
 
         // Apply gateways
@@ -792,6 +1451,7 @@ impl Inner {
                 .update_servers(&dns_servers[..count]);
         }
 
+        self.config_generation = self.config_generation.wrapping_add(1);
         self.state_waker.wake();
     }
 
@@ -815,6 +1475,12 @@ impl Inner {
             }
         }
 
+        let promiscuous_changed = self.promiscuous.apply(|enabled| driver.set_promiscuous(enabled));
+        let all_multicast_changed = self.all_multicast.apply(|enabled| driver.set_all_multicast(enabled));
+        if promiscuous_changed || all_multicast_changed {
+            self.mode_waker.wake();
+        }
+
         let timestamp = instant_to_smoltcp(Instant::now());
         let mut smoldev = DriverAdapter {
             cx: Some(cx),
@@ -822,6 +1488,8 @@ impl Inner {
             medium,
         };
         self.iface.poll(timestamp, &mut smoldev, &mut self.sockets);
+        self.device_stats = driver.stats();
+        self.last_device_error = driver.last_tx_error();
 
         // Update link up
         let old_link_up = self.link_up;
@@ -883,10 +1551,155 @@ impl<'d, D: Driver> Runner<'d, D> {
     /// You must call this in a background task, to process network events.
     pub async fn run(&mut self) -> ! {
         poll_fn(|cx| {
-            self.stack.with_mut(|i| i.poll(cx, &mut self.driver));
+            self.stack.with_mut(|i| {
+                i.stopped = false;
+                i.stop_requested = false;
+                i.poll(cx, &mut self.driver)
+            });
             Poll::<()>::Pending
         })
         .await;
         unreachable!()
     }
+
+    /// Run the network stack until [`Stack::request_stop`] is called.
+    ///
+    /// Like [`run`](Self::run), but once a stop is requested, this keeps processing network
+    /// events until the transmit buffers of all UDP sockets are empty, or `drain_timeout` has
+    /// passed, e.g. because the link is down, then returns.
+    ///
+    /// When it returns, datagrams still queued in UDP sockets are discarded, and pending UDP
+    /// socket operations fail with `RecvError::Stopped` or `SendError::Stopped`, as do new
+    /// ones, until the stack runs again. UDP sockets stay bound. TCP connections are aborted,
+    /// and pending and new TCP socket operations fail with a `Stopped` error too. Waits without
+    /// an error to report, like [`wait_recv_ready`](crate::udp::UdpSocket::wait_recv_ready),
+    /// complete instead.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::join::join;
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, RecvError, SendError, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use embassy_time::{Duration, Instant};
+    ///
+    /// let config = Config::ipv4_static(StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway: None,
+    ///     dns_servers: Default::default(),
+    /// });
+    /// // Nobody receives from the peer device, so only one frame can ever be transmitted.
+    /// let state = LoopbackState::<1500, 1>::new();
+    /// let (dev, _peer) = state.pair();
+    /// let mut resources = StackResources::<2>::new();
+    /// let (stack, mut runner) = embassy_net::new(dev, config, &mut resources, 1);
+    ///
+    /// let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    /// let mut rx = [0; 256];
+    /// let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    /// let mut tx = [0; 256];
+    /// let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+    /// socket.bind(1234).unwrap();
+    /// let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    ///
+    /// let start = Instant::now();
+    /// let app = async {
+    ///     for _ in 0..3 {
+    ///         socket.send_to(b"data", remote).await.unwrap();
+    ///     }
+    ///     stack.request_stop();
+    ///     let mut buf = [0; 16];
+    ///     assert_eq!(socket.recv_from(&mut buf).await, Err(RecvError::Stopped));
+    ///     assert_eq!(socket.send_to(b"data", remote).await, Err(SendError::Stopped));
+    /// };
+    /// block_on(join(runner.run_until_stopped(Duration::from_millis(50)), app));
+    /// // The queued datagrams could never be sent, so the drain timed out.
+    /// assert!(start.elapsed() >= Duration::from_millis(50));
+    /// # }
+    /// ```
+    ///
+    /// A pending TCP read fails once the stack is stopped:
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4", feature = "tcp"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::join::join;
+    /// use embassy_futures::select::{select, Either};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::tcp::{ConnectError, Error, TcpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use embassy_time::Duration;
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let (mut rx_a, mut tx_a) = ([0; 256], [0; 256]);
+    /// let mut a = TcpSocket::new(stack_a, &mut rx_a, &mut tx_a);
+    /// let (mut rx_b, mut tx_b) = ([0; 256], [0; 256]);
+    /// let mut b = TcpSocket::new(stack_b, &mut rx_b, &mut tx_b);
+    ///
+    /// let app = async {
+    ///     let (accepted, connected) = join(a.accept(80), b.connect((Ipv4Address::new(10, 0, 0, 1), 80))).await;
+    ///     accepted.unwrap();
+    ///     connected.unwrap();
+    ///
+    ///     // Nothing is ever sent, so the read waits until the stack stops.
+    ///     let mut buf = [0; 16];
+    ///     let (read, ()) = join(a.read(&mut buf), async { stack_a.request_stop() }).await;
+    ///     assert_eq!(read, Err(Error::Stopped));
+    ///     assert_eq!(a.write(b"data").await, Err(Error::Stopped));
+    ///     assert_eq!(a.connect((Ipv4Address::new(10, 0, 0, 2), 80)).await, Err(ConnectError::Stopped));
+    /// };
+    /// let stopped = join(runner_a.run_until_stopped(Duration::from_millis(50)), app);
+    /// match block_on(select(stopped, runner_b.run())) {
+    ///     Either::First(_) => {}
+    ///     Either::Second(_) => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub async fn run_until_stopped(&mut self, drain_timeout: Duration) {
+        self.stack.with_mut(|i| i.stopped = false);
+        let mut deadline = None;
+        poll_fn(|cx| {
+            self.stack.with_mut(|i| {
+                i.poll(cx, &mut self.driver);
+                if !i.stop_requested {
+                    return Poll::Pending;
+                }
+                let deadline = *deadline.get_or_insert_with(|| Instant::now() + drain_timeout);
+                if i.udp_tx_idle() || Instant::now() >= deadline {
+                    i.stop();
+                    Poll::Ready(())
+                } else {
+                    // The first poll of a timer always schedules a wakeup at its expiry.
+                    let _ = pin!(Timer::at(deadline)).poll(cx);
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Consume the runner, returning the driver.
+    ///
+    /// This allows tearing the stack down after [`run_until_stopped`](Self::run_until_stopped)
+    /// returned, e.g. to reuse the driver with a new stack.
+    pub fn into_driver(self) -> D {
+        self.driver
+    }
 }
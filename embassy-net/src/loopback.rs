@@ -0,0 +1,431 @@
+//! Loopback driver for host-side testing.
+//!
+//! [`LoopbackDevice`] is an IP-medium [`Driver`] that does not talk to any hardware. Frames
+//! transmitted by a device are queued for reception either by the same device, or by its peer
+//! when created with [`LoopbackState::pair`]. [`LoopbackState::pair_ethernet`] creates an
+//! Ethernet-medium pair instead, which also exercises neighbor discovery. This allows exercising the stack and its sockets
+//! in a plain `cargo test`.
+//!
+//! ## Usage
+//!
+//! ```
+//! use embassy_futures::block_on;
+//! use embassy_futures::select::{select3, Either3};
+//! use embassy_net::loopback::LoopbackState;
+//! use embassy_net::udp::{PacketMetadata, UdpSocket};
+//! use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+//!
+//! fn config(addr: Ipv4Address) -> Config {
+//!     Config::ipv4_static(StaticConfigV4 {
+//!         address: Ipv4Cidr::new(addr, 24),
+//!         gateway: None,
+//!         dns_servers: Default::default(),
+//!     })
+//! }
+//!
+//! let state = LoopbackState::<1500, 4>::new();
+//! let (dev_a, dev_b) = state.pair();
+//!
+//! let mut resources_a = StackResources::<2>::new();
+//! let mut resources_b = StackResources::<2>::new();
+//! let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+//! let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+//! assert_eq!(stack_a.udp_payload_mtu(Ipv4Address::new(10, 0, 0, 2).into()), 1500 - 20 - 8);
+//! assert!(stack_a.has_route_to(Ipv4Address::new(10, 0, 0, 2).into()));
+//! assert!(!stack_a.has_route_to(Ipv4Address::new(192, 168, 1, 1).into()));
+//!
+//! let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+//! let mut rx_a = [0; 1024];
+//! let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+//! let mut tx_a = [0; 1024];
+//! let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+//! a.bind(1234).unwrap();
+//!
+//! let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+//! let mut rx_b = [0; 1024];
+//! let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+//! let mut tx_b = [0; 1024];
+//! let mut b = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+//! b.bind(5678).unwrap();
+//!
+//! let test = async {
+//!     a.send_to(b"hello", (Ipv4Address::new(10, 0, 0, 2), 5678)).await.unwrap();
+//!     let mut buf = [0; 16];
+//!     let (n, meta) = b.recv_from(&mut buf).await.unwrap();
+//!     assert_eq!(&buf[..n], b"hello");
+//!     assert_eq!(meta.endpoint.port, 1234);
+//!
+//!     // Empty datagrams are delivered too.
+//!     a.send_to(&[], (Ipv4Address::new(10, 0, 0, 2), 5678)).await.unwrap();
+//!     let (n, meta) = b.recv_from(&mut buf).await.unwrap();
+//!     assert_eq!(n, 0);
+//!     assert_eq!(meta.endpoint, (Ipv4Address::new(10, 0, 0, 1), 1234).into());
+//! };
+//!
+//! match block_on(select3(test, runner_a.run(), runner_b.run())) {
+//!     Either3::First(()) => {}
+//!     _ => unreachable!(),
+//! }
+//! assert_eq!(stack_b.device_stats().rx_frames, 2);
+//! ```
+//!
+//! ## Packet loss and latency
+//!
+//! [`LoopbackState::set_drop_every`] drops every `n`-th transmitted frame, and
+//! [`LoopbackState::set_latency`] delays the reception of every frame.
+//!
+//! ```
+//! use embassy_futures::block_on;
+//! use embassy_futures::select::{select3, Either3};
+//! use embassy_net::loopback::LoopbackState;
+//! use embassy_net::udp::{PacketMetadata, UdpSocket};
+//! use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+//! use embassy_time::{Duration, Instant};
+//!
+//! fn config(addr: Ipv4Address) -> Config {
+//!     Config::ipv4_static(StaticConfigV4 {
+//!         address: Ipv4Cidr::new(addr, 24),
+//!         gateway: None,
+//!         dns_servers: Default::default(),
+//!     })
+//! }
+//!
+//! let state = LoopbackState::<1500, 4>::new();
+//! state.set_latency(Duration::from_millis(50));
+//! let (dev_a, dev_b) = state.pair();
+//!
+//! let mut resources_a = StackResources::<2>::new();
+//! let mut resources_b = StackResources::<2>::new();
+//! let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+//! let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+//!
+//! let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+//! let mut rx_a = [0; 1024];
+//! let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+//! let mut tx_a = [0; 1024];
+//! let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+//! a.bind(1234).unwrap();
+//!
+//! let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+//! let mut rx_b = [0; 1024];
+//! let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+//! let mut tx_b = [0; 1024];
+//! let mut b = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+//! b.bind(5678).unwrap();
+//!
+//! let test = async {
+//!     // Nothing listens on port 9, so the peer answers from within its receive path
+//!     // with an ICMP port unreachable message.
+//!     a.send_to(b"discard", (Ipv4Address::new(10, 0, 0, 2), 9)).await.unwrap();
+//!
+//!     let start = Instant::now();
+//!     a.send_to(b"hello", (Ipv4Address::new(10, 0, 0, 2), 5678)).await.unwrap();
+//!     let mut buf = [0; 16];
+//!     let (n, _) = b.recv_from(&mut buf).await.unwrap();
+//!     assert_eq!(&buf[..n], b"hello");
+//!     assert!(start.elapsed() >= Duration::from_millis(50));
+//! };
+//!
+//! match block_on(select3(test, runner_a.run(), runner_b.run())) {
+//!     Either3::First(()) => {}
+//!     _ => unreachable!(),
+//! }
+//! ```
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState, Stats};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::{Deque, Vec};
+
+/// Loopback state.
+///
+/// Holds up to `N` queued frames of at most `MTU` bytes in each direction.
+pub struct LoopbackState<const MTU: usize, const N: usize> {
+    shared: Mutex<NoopRawMutex, RefCell<Shared<MTU, N>>>,
+}
+
+struct Shared<const MTU: usize, const N: usize> {
+    queues: [Queue<MTU, N>; 2],
+    drop_every: u32,
+    tx_count: u32,
+    latency: Duration,
+}
+
+struct Queue<const MTU: usize, const N: usize> {
+    /// Queued frames, along with the instant they become receivable.
+    frames: Deque<(Instant, Vec<u8, MTU>), N>,
+    rx_waker: WakerRegistration,
+    tx_waker: WakerRegistration,
+    pushed: u32,
+    popped: u32,
+    dropped: u32,
+}
+
+impl<const MTU: usize, const N: usize> Queue<MTU, N> {
+    const fn new() -> Self {
+        Self {
+            frames: Deque::new(),
+            rx_waker: WakerRegistration::new(),
+            tx_waker: WakerRegistration::new(),
+            pushed: 0,
+            popped: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl<const MTU: usize, const N: usize> LoopbackState<MTU, N> {
+    /// Create a new loopback state.
+    pub const fn new() -> Self {
+        Self {
+            shared: Mutex::new(RefCell::new(Shared {
+                queues: [Queue::new(), Queue::new()],
+                drop_every: 0,
+                tx_count: 0,
+                latency: Duration::from_ticks(0),
+            })),
+        }
+    }
+
+    /// Create a device receiving its own transmitted frames.
+    pub fn device(&self) -> LoopbackDevice<'_, MTU, N> {
+        LoopbackDevice {
+            shared: &self.shared,
+            rx: 0,
+            tx: 0,
+            hardware_address: HardwareAddress::Ip,
+        }
+    }
+
+    /// Create two devices connected to each other.
+    ///
+    /// Frames transmitted by one device are received by the other.
+    pub fn pair(&self) -> (LoopbackDevice<'_, MTU, N>, LoopbackDevice<'_, MTU, N>) {
+        (
+            LoopbackDevice {
+                shared: &self.shared,
+                rx: 0,
+                tx: 1,
+                hardware_address: HardwareAddress::Ip,
+            },
+            LoopbackDevice {
+                shared: &self.shared,
+                rx: 1,
+                tx: 0,
+                hardware_address: HardwareAddress::Ip,
+            },
+        )
+    }
+
+    /// Create two Ethernet-medium devices connected to each other.
+    ///
+    /// Like [`pair`](Self::pair), but the devices have the MAC addresses `02:00:00:00:00:01` and
+    /// `02:00:00:00:00:02`, and `MTU` includes the Ethernet header.
+    #[cfg(feature = "medium-ethernet")]
+    pub fn pair_ethernet(&self) -> (LoopbackDevice<'_, MTU, N>, LoopbackDevice<'_, MTU, N>) {
+        (
+            LoopbackDevice {
+                shared: &self.shared,
+                rx: 0,
+                tx: 1,
+                hardware_address: HardwareAddress::Ethernet([2, 0, 0, 0, 0, 1]),
+            },
+            LoopbackDevice {
+                shared: &self.shared,
+                rx: 1,
+                tx: 0,
+                hardware_address: HardwareAddress::Ethernet([2, 0, 0, 0, 0, 2]),
+            },
+        )
+    }
+
+    /// Drop every `n`-th transmitted frame, counting frames of all devices of this state.
+    ///
+    /// `0` disables packet loss, which is the default.
+    pub fn set_drop_every(&self, n: u32) {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.drop_every = n;
+            s.tx_count = 0;
+        })
+    }
+
+    /// Delay the reception of every transmitted frame by `latency`.
+    ///
+    /// Applies to frames transmitted from now on. A zero latency, the default, makes frames
+    /// receivable as soon as they are transmitted.
+    pub fn set_latency(&self, latency: Duration) {
+        self.shared.lock(|s| s.borrow_mut().latency = latency)
+    }
+}
+
+impl<const MTU: usize, const N: usize> Default for LoopbackState<MTU, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Loopback device.
+///
+/// Created by [`LoopbackState::device`], [`LoopbackState::pair`] or
+/// [`LoopbackState::pair_ethernet`].
+pub struct LoopbackDevice<'d, const MTU: usize, const N: usize> {
+    shared: &'d Mutex<NoopRawMutex, RefCell<Shared<MTU, N>>>,
+    rx: usize,
+    tx: usize,
+    hardware_address: HardwareAddress,
+}
+
+impl<'d, const MTU: usize, const N: usize> Driver for LoopbackDevice<'d, MTU, N> {
+    type RxToken<'a>
+        = RxToken<'a, MTU, N>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a, MTU, N>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let ready = self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            let can_recv = match s.queues[self.rx].frames.front() {
+                Some(&(at, _)) if at > Instant::now() => {
+                    // The first poll of a timer always schedules a wakeup at its expiry.
+                    let _ = Pin::new(&mut Timer::at(at)).poll(cx);
+                    false
+                }
+                Some(_) => true,
+                None => {
+                    s.queues[self.rx].rx_waker.register(cx.waker());
+                    false
+                }
+            };
+            let can_send = !s.queues[self.tx].frames.is_full();
+            if !can_send {
+                s.queues[self.tx].tx_waker.register(cx.waker());
+            }
+            can_recv && can_send
+        });
+
+        ready.then_some((
+            RxToken {
+                shared: self.shared,
+                queue: self.rx,
+            },
+            TxToken {
+                shared: self.shared,
+                queue: self.tx,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        let ready = self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            let can_send = !s.queues[self.tx].frames.is_full();
+            if !can_send {
+                s.queues[self.tx].tx_waker.register(cx.waker());
+            }
+            can_send
+        });
+
+        ready.then_some(TxToken {
+            shared: self.shared,
+            queue: self.tx,
+        })
+    }
+
+    fn link_state(&mut self, _cx: &mut Context) -> LinkState {
+        LinkState::Up
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut caps = Capabilities::default();
+        caps.max_transmission_unit = MTU;
+        caps
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.hardware_address
+    }
+
+    fn stats(&self) -> Stats {
+        self.shared.lock(|s| {
+            let s = s.borrow();
+            let mut stats = Stats::default();
+            stats.tx_frames = s.queues[self.tx].pushed;
+            stats.tx_dropped = s.queues[self.tx].dropped;
+            stats.rx_frames = s.queues[self.rx].popped;
+            stats
+        })
+    }
+}
+
+/// Loopback rx token.
+pub struct RxToken<'a, const MTU: usize, const N: usize> {
+    shared: &'a Mutex<NoopRawMutex, RefCell<Shared<MTU, N>>>,
+    queue: usize,
+}
+
+impl<'a, const MTU: usize, const N: usize> embassy_net_driver::RxToken for RxToken<'a, MTU, N> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let (_, mut frame) = self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            let queue = &mut s.queues[self.queue];
+            // NOTE(unwrap): we checked the queue wasn't empty when creating the token.
+            let frame = unwrap!(queue.frames.pop_front());
+            queue.popped = queue.popped.wrapping_add(1);
+            queue.tx_waker.wake();
+            frame
+        });
+        // Release the lock first, the stack may consume the paired tx token to send a reply.
+        f(&mut frame)
+    }
+}
+
+/// Loopback tx token.
+pub struct TxToken<'a, const MTU: usize, const N: usize> {
+    shared: &'a Mutex<NoopRawMutex, RefCell<Shared<MTU, N>>>,
+    queue: usize,
+}
+
+impl<'a, const MTU: usize, const N: usize> embassy_net_driver::TxToken for TxToken<'a, MTU, N> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.tx_count = s.tx_count.wrapping_add(1);
+            let dropped = s.drop_every != 0 && s.tx_count % s.drop_every == 0;
+
+            let at = Instant::now() + s.latency;
+            let queue = &mut s.queues[self.queue];
+            // NOTE(unwrap): we checked the queue wasn't full when creating the token.
+            unwrap!(queue.frames.push_back((at, Vec::new())).ok());
+            let (_, frame) = unwrap!(queue.frames.back_mut());
+            unwrap!(frame.resize(len, 0).ok());
+            let r = f(frame);
+
+            if dropped {
+                queue.frames.pop_back();
+                queue.dropped = queue.dropped.wrapping_add(1);
+            } else {
+                queue.pushed = queue.pushed.wrapping_add(1);
+                queue.rx_waker.wake();
+            }
+            r
+        })
+    }
+}
@@ -28,6 +28,91 @@ pub struct RawSocket<'a> {
 
 impl<'a> RawSocket<'a> {
     /// Create a new Raw socket using the provided stack and buffers.
+    ///
+    /// Packets are sent and received with their IP header. On send, the header checksum is
+    /// filled in by the stack.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::{LoopbackDevice, LoopbackState};
+    /// use embassy_net::raw::{IpProtocol, IpVersion, PacketMetadata, RawSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// // Protocol 253 is reserved for experimentation.
+    /// let protocol = IpProtocol::Unknown(253);
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 256];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 256];
+    /// let a = RawSocket::new::<LoopbackDevice<'_, 1500, 4>>(
+    ///     stack_a,
+    ///     IpVersion::Ipv4,
+    ///     protocol,
+    ///     &mut rx_meta_a,
+    ///     &mut rx_a,
+    ///     &mut tx_meta_a,
+    ///     &mut tx_a,
+    /// );
+    ///
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 256];
+    /// let b = RawSocket::new::<LoopbackDevice<'_, 1500, 4>>(
+    ///     stack_b,
+    ///     IpVersion::Ipv4,
+    ///     protocol,
+    ///     &mut rx_meta_b,
+    ///     &mut rx_b,
+    ///     &mut tx_meta_b,
+    ///     &mut tx_b,
+    /// );
+    ///
+    /// let payload = b"raw payload";
+    /// let mut packet = [0; 20 + 11];
+    /// let len = packet.len() as u16;
+    /// packet[..20].copy_from_slice(&[
+    ///     0x45, 0, (len >> 8) as u8, len as u8, // version, header length, total length
+    ///     0, 0, 0x40, 0, // identification, don't fragment
+    ///     64, 253, 0, 0, // hop limit, protocol, checksum
+    ///     10, 0, 0, 1, // source
+    ///     10, 0, 0, 2, // destination
+    /// ]);
+    /// packet[20..].copy_from_slice(payload);
+    ///
+    /// let test = async {
+    ///     a.send(&packet).await;
+    ///     let mut buf = [0; 64];
+    ///     let n = b.recv(&mut buf).await.unwrap();
+    ///     assert_eq!(n, packet.len());
+    ///     assert_eq!(buf[9], 253);
+    ///     assert_eq!(&buf[12..20], &packet[12..20]);
+    ///     assert_eq!(&buf[20..n], payload);
+    /// };
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
     pub fn new<D: Driver>(
         stack: Stack<'a>,
         ip_version: IpVersion,
@@ -29,6 +29,8 @@ pub enum Error {
     ///
     /// This can happen on receiving a RST packet, or on timeout.
     ConnectionReset,
+    /// The stack was stopped, see [`Runner::run_until_stopped`](crate::Runner::run_until_stopped).
+    Stopped,
 }
 
 /// Error returned by [`TcpSocket::connect`].
@@ -43,6 +45,10 @@ pub enum ConnectError {
     TimedOut,
     /// No route to host.
     NoRoute,
+    /// All ports of the dynamic range are in use.
+    NoFreePort,
+    /// The stack was stopped, see [`Runner::run_until_stopped`](crate::Runner::run_until_stopped).
+    Stopped,
 }
 
 /// Error returned by [`TcpSocket::accept`].
@@ -55,6 +61,8 @@ pub enum AcceptError {
     InvalidPort,
     /// The remote host rejected the connection with a RST packet.
     ConnectionReset,
+    /// The stack was stopped, see [`Runner::run_until_stopped`](crate::Runner::run_until_stopped).
+    Stopped,
 }
 
 /// A TCP socket.
@@ -244,7 +252,9 @@ impl<'a> TcpSocket<'a> {
     where
         T: Into<IpEndpoint>,
     {
-        let local_port = self.io.stack.with_mut(|i| i.get_local_port());
+        let Some(local_port) = self.io.stack.with_mut(|i| i.get_local_port()) else {
+            return Err(ConnectError::NoFreePort);
+        };
 
         match {
             self.io
@@ -256,6 +266,9 @@ impl<'a> TcpSocket<'a> {
         }
 
         poll_fn(|cx| {
+            if self.io.stopped() {
+                return Poll::Ready(Err(ConnectError::Stopped));
+            }
             self.io.with_mut(|s, _| match s.state() {
                 tcp::State::Closed | tcp::State::TimeWait => Poll::Ready(Err(ConnectError::ConnectionReset)),
                 tcp::State::Listen => unreachable!(),
@@ -283,6 +296,9 @@ impl<'a> TcpSocket<'a> {
         }
 
         poll_fn(|cx| {
+            if self.io.stopped() {
+                return Poll::Ready(Err(AcceptError::Stopped));
+            }
             self.io.with_mut(|s, _| match s.state() {
                 tcp::State::Listen | tcp::State::SynSent | tcp::State::SynReceived => {
                     s.register_send_waker(cx.waker());
@@ -490,9 +506,15 @@ impl<'d> TcpIo<'d> {
         })
     }
 
+    /// Whether the stack was stopped by [`Runner::run_until_stopped`](crate::Runner::run_until_stopped).
+    fn stopped(&self) -> bool {
+        self.stack.with(|i| i.stopped)
+    }
+
     fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let stopped = self.stopped();
         self.with_mut(|s, _| {
-            if s.can_recv() {
+            if s.can_recv() || stopped {
                 Poll::Ready(())
             } else {
                 s.register_recv_waker(cx.waker());
@@ -503,6 +525,9 @@ impl<'d> TcpIo<'d> {
 
     fn read<'s>(&'s mut self, buf: &'s mut [u8]) -> impl Future<Output = Result<usize, Error>> + 's {
         poll_fn(|cx| {
+            if self.stopped() {
+                return Poll::Ready(Err(Error::Stopped));
+            }
             // CAUTION: smoltcp semantics around EOF are different to what you'd expect
             // from posix-like IO, so we have to tweak things here.
             self.with_mut(|s, _| match s.recv_slice(buf) {
@@ -529,8 +554,9 @@ impl<'d> TcpIo<'d> {
     }
 
     fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let stopped = self.stopped();
         self.with_mut(|s, _| {
-            if s.can_send() {
+            if s.can_send() || stopped {
                 Poll::Ready(())
             } else {
                 s.register_send_waker(cx.waker());
@@ -541,6 +567,9 @@ impl<'d> TcpIo<'d> {
 
     fn write<'s>(&'s mut self, buf: &'s [u8]) -> impl Future<Output = Result<usize, Error>> + 's {
         poll_fn(|cx| {
+            if self.stopped() {
+                return Poll::Ready(Err(Error::Stopped));
+            }
             self.with_mut(|s, _| match s.send_slice(buf) {
                 // Not ready to send (no space in the tx buffer)
                 Ok(0) => {
@@ -561,6 +590,9 @@ impl<'d> TcpIo<'d> {
     {
         let mut f = Some(f);
         poll_fn(move |cx| {
+            if self.stopped() {
+                return Poll::Ready(Err(Error::Stopped));
+            }
             self.with_mut(|s, _| {
                 if !s.can_send() {
                     if s.may_send() {
@@ -589,6 +621,9 @@ impl<'d> TcpIo<'d> {
     {
         let mut f = Some(f);
         poll_fn(move |cx| {
+            if self.stopped() {
+                return Poll::Ready(Err(Error::Stopped));
+            }
             self.with_mut(|s, _| {
                 if !s.can_recv() {
                     if s.may_recv() {
@@ -615,6 +650,9 @@ impl<'d> TcpIo<'d> {
 
     fn flush(&mut self) -> impl Future<Output = Result<(), Error>> + '_ {
         poll_fn(|cx| {
+            if self.stopped() {
+                return Poll::Ready(Err(Error::Stopped));
+            }
             self.with_mut(|s, _| {
                 let data_pending = (s.send_queue() > 0) && s.state() != tcp::State::Closed;
                 let fin_pending = matches!(
@@ -663,6 +701,8 @@ mod embedded_io_impls {
                 ConnectError::TimedOut => embedded_io_async::ErrorKind::TimedOut,
                 ConnectError::NoRoute => embedded_io_async::ErrorKind::NotConnected,
                 ConnectError::InvalidState => embedded_io_async::ErrorKind::Other,
+                ConnectError::NoFreePort => embedded_io_async::ErrorKind::AddrInUse,
+                ConnectError::Stopped => embedded_io_async::ErrorKind::ConnectionAborted,
             }
         }
     }
@@ -671,6 +711,7 @@ mod embedded_io_impls {
         fn kind(&self) -> embedded_io_async::ErrorKind {
             match self {
                 Error::ConnectionReset => embedded_io_async::ErrorKind::ConnectionReset,
+                Error::Stopped => embedded_io_async::ErrorKind::ConnectionAborted,
             }
         }
     }
@@ -746,12 +787,11 @@ mod embedded_io_impls {
 
 /// TCP client compatible with `embedded-nal-async` traits.
 pub mod client {
-    use core::cell::{Cell, UnsafeCell};
-    use core::mem::MaybeUninit;
     use core::net::IpAddr;
     use core::ptr::NonNull;
 
     use super::*;
+    use crate::pool::Pool;
 
     /// TCP client connection pool compatible with `embedded-nal-async` traits.
     ///
@@ -877,44 +917,4 @@ pub mod client {
             Self { pool: Pool::new() }
         }
     }
-
-    struct Pool<T, const N: usize> {
-        used: [Cell<bool>; N],
-        data: [UnsafeCell<MaybeUninit<T>>; N],
-    }
-
-    impl<T, const N: usize> Pool<T, N> {
-        const VALUE: Cell<bool> = Cell::new(false);
-        const UNINIT: UnsafeCell<MaybeUninit<T>> = UnsafeCell::new(MaybeUninit::uninit());
-
-        const fn new() -> Self {
-            Self {
-                used: [Self::VALUE; N],
-                data: [Self::UNINIT; N],
-            }
-        }
-    }
-
-    impl<T, const N: usize> Pool<T, N> {
-        fn alloc(&self) -> Option<NonNull<T>> {
-            for n in 0..N {
-                // this can't race because Pool is not Sync.
-                if !self.used[n].get() {
-                    self.used[n].set(true);
-                    let p = self.data[n].get() as *mut T;
-                    return Some(unsafe { NonNull::new_unchecked(p) });
-                }
-            }
-            None
-        }
-
-        /// safety: p must be a pointer obtained from self.alloc that hasn't been freed yet.
-        unsafe fn free(&self, p: NonNull<T>) {
-            let origin = self.data.as_ptr() as *mut T;
-            let n = p.as_ptr().offset_from(origin);
-            assert!(n >= 0);
-            assert!((n as usize) < N);
-            self.used[n as usize].set(false);
-        }
-    }
 }
@@ -1,16 +1,53 @@
 //! UDP sockets.
 
+use core::cell::{Cell, RefCell, UnsafeCell};
 use core::future::{poll_fn, Future};
-use core::mem;
-use core::task::{Context, Poll};
+use core::mem::{self, ManuallyDrop};
+use core::ops::Deref;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll, Waker};
 
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, WithTimeout};
+use heapless::Vec;
+#[cfg(feature = "multicast")]
+use smoltcp::config::IFACE_MAX_MULTICAST_GROUP_COUNT;
+#[cfg(feature = "multicast")]
+use smoltcp::iface::MulticastError;
 use smoltcp::iface::{Interface, SocketHandle};
 use smoltcp::socket::udp;
 pub use smoltcp::socket::udp::{PacketMetadata, UdpMetadata};
-use smoltcp::wire::IpListenEndpoint;
+#[cfg(feature = "proto-ipv6")]
+use smoltcp::wire::Ipv6Address;
+use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint};
+#[cfg(feature = "proto-ipv4")]
+use smoltcp::wire::{IpCidr, Ipv4Address};
 
+use crate::pool::Pool;
 use crate::Stack;
 
+/// Number of tasks a socket queues while they wait for transmit buffer space.
+const SEND_WAITERS: usize = 4;
+
+/// Longest host name a [`HostCache`] caches the address of.
+#[cfg(feature = "dns")]
+const HOST_CACHE_LEN: usize = 64;
+
+/// Convert a resolved address, if the stack supports its address family.
+#[cfg(feature = "dns")]
+fn resolved_address(addr: core::net::IpAddr) -> Option<IpAddress> {
+    match addr {
+        #[cfg(feature = "proto-ipv4")]
+        core::net::IpAddr::V4(addr) => Some(IpAddress::Ipv4(addr)),
+        #[cfg(feature = "proto-ipv6")]
+        core::net::IpAddr::V6(addr) => Some(IpAddress::Ipv6(addr)),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
 /// Error returned by [`UdpSocket::bind`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -19,8 +56,24 @@ pub enum BindError {
     InvalidState,
     /// No route to host.
     NoRoute,
+    /// All ports of the dynamic range are in use.
+    NoFreePort,
+}
+
+impl core::fmt::Display for BindError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            Self::InvalidState => "Socket Already Open",
+            Self::NoRoute => "No Route To Host",
+            Self::NoFreePort => "No Free Port",
+        };
+
+        write!(f, "{message}")
+    }
 }
 
+impl core::error::Error for BindError {}
+
 /// Error returned by [`UdpSocket::send_to`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -31,24 +84,697 @@ pub enum SendError {
     SocketNotBound,
     /// There is not enough transmit buffer capacity to ever send this packet.
     PacketTooLarge,
+    /// Socket has no default remote endpoint, see [`UdpSocket::connect`].
+    NotConnected,
+    /// The stack was stopped, see [`Runner::run_until_stopped`](crate::Runner::run_until_stopped).
+    Stopped,
+}
+
+impl core::fmt::Display for SendError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            Self::NoRoute => "No Route To Host",
+            Self::SocketNotBound => "Socket Not Bound",
+            Self::PacketTooLarge => "Packet Larger Than Transmit Buffer",
+            Self::NotConnected => "Socket Not Connected",
+            Self::Stopped => "Stack Stopped",
+        };
+
+        write!(f, "{message}")
+    }
+}
+
+impl core::error::Error for SendError {}
+
+/// Error returned by [`UdpSocket::send_to_all`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SendAllError {
+    /// Number of endpoints the datagram was queued for before the error.
+    pub sent: usize,
+    /// The error that stopped the send.
+    pub error: SendError,
+}
+
+impl core::fmt::Display for SendAllError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} After Sending To {} Endpoints", self.error, self.sent)
+    }
+}
+
+impl core::error::Error for SendAllError {}
+
+/// Error returned by [`UdpSocket::send_to_host`].
+#[cfg(feature = "dns")]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SendToHostError<E> {
+    /// The resolver failed to resolve the host name.
+    Resolve(E),
+    /// Sending the datagram failed.
+    Send(SendError),
+}
+
+#[cfg(feature = "dns")]
+impl<E: core::fmt::Display> core::fmt::Display for SendToHostError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Resolve(e) => write!(f, "Resolve Failed: {e}"),
+            Self::Send(e) => write!(f, "Send Failed: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "dns")]
+impl<E: core::fmt::Debug + core::fmt::Display> core::error::Error for SendToHostError<E> {}
+
+/// Address cache for [`UdpSocket::send_to_host`].
+///
+/// Holds the address of the most recently resolved host, if its name is at most 64 bytes
+/// long. The entry is dropped when the stack's [configuration generation](Stack::config_generation)
+/// changes, e.g. because DHCP obtained a new lease with different DNS servers.
+#[cfg(feature = "dns")]
+#[derive(Clone, Debug, Default)]
+pub struct HostCache {
+    entry: Option<(heapless::String<HOST_CACHE_LEN>, IpAddress, u32)>,
+}
+
+#[cfg(feature = "dns")]
+impl HostCache {
+    /// Create an empty cache.
+    pub const fn new() -> Self {
+        Self { entry: None }
+    }
+
+    /// Remove the cached address, so the next send resolves the host again.
+    pub fn clear(&mut self) {
+        self.entry = None;
+    }
+
+    fn get(&self, host: &str, generation: u32) -> Option<IpAddress> {
+        self.entry
+            .as_ref()
+            .filter(|(name, _, g)| name == host && *g == generation)
+            .map(|(_, addr, _)| *addr)
+    }
+
+    fn insert(&mut self, host: &str, addr: IpAddress, generation: u32) {
+        self.entry = heapless::String::try_from(host)
+            .ok()
+            .map(|name| (name, addr, generation));
+    }
+}
+
+/// Error returned by [`UdpSocket::send_to_before`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SendTimeoutError {
+    /// The datagram could not be queued before the deadline.
+    Timeout,
+    /// No route to host.
+    NoRoute,
+    /// Socket not bound to an outgoing port.
+    SocketNotBound,
+    /// There is not enough transmit buffer capacity to ever send this packet.
+    PacketTooLarge,
+    /// Socket has no default remote endpoint.
+    NotConnected,
+    /// The stack was stopped.
+    Stopped,
+}
+
+impl core::fmt::Display for SendTimeoutError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            Self::Timeout => "Send Timed Out",
+            Self::NoRoute => "No Route To Host",
+            Self::SocketNotBound => "Socket Not Bound",
+            Self::PacketTooLarge => "Packet Larger Than Transmit Buffer",
+            Self::NotConnected => "Socket Not Connected",
+            Self::Stopped => "Stack Stopped",
+        };
+
+        write!(f, "{message}")
+    }
+}
+
+impl core::error::Error for SendTimeoutError {}
+
+impl From<SendError> for SendTimeoutError {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::NoRoute => SendTimeoutError::NoRoute,
+            SendError::SocketNotBound => SendTimeoutError::SocketNotBound,
+            SendError::PacketTooLarge => SendTimeoutError::PacketTooLarge,
+            SendError::NotConnected => SendTimeoutError::NotConnected,
+            SendError::Stopped => SendTimeoutError::Stopped,
+        }
+    }
+}
+
+/// Error returned by [`UdpSocket::try_new`] and [`UdpSocket::new_pooled`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NewSocketError {
+    /// All sockets of the stack are in use.
+    NoFreeSocket,
+    /// One of the buffers is empty, so the socket could never send or receive.
+    EmptyBuffer,
+    /// All buffers of the [`UdpBufferPool`] are lent to other sockets.
+    PoolExhausted,
+}
+
+impl core::fmt::Display for NewSocketError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            Self::NoFreeSocket => "No Free Socket",
+            Self::EmptyBuffer => "Empty Socket Buffer",
+            Self::PoolExhausted => "Buffer Pool Exhausted",
+        };
+
+        write!(f, "{message}")
+    }
 }
 
+impl core::error::Error for NewSocketError {}
+
 /// Error returned by [`UdpSocket::recv_from`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RecvError {
     /// Provided buffer was smaller than the received packet.
     Truncated,
+    /// The stack was stopped, see [`Runner::run_until_stopped`](crate::Runner::run_until_stopped).
+    Stopped,
+}
+
+impl core::fmt::Display for RecvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            Self::Truncated => "Buffer Smaller Than Packet",
+            Self::Stopped => "Stack Stopped",
+        };
+
+        write!(f, "{message}")
+    }
+}
+
+impl core::error::Error for RecvError {}
+
+/// Error returned by [`UdpSocket::recv_from_with_timeout`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecvTimeoutError {
+    /// No datagram was received before the timeout expired.
+    Timeout,
+    /// Provided buffer was smaller than the received packet.
+    Truncated,
+    /// The stack was stopped.
+    Stopped,
+}
+
+impl core::fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            Self::Timeout => "Receive Timed Out",
+            Self::Truncated => "Buffer Smaller Than Packet",
+            Self::Stopped => "Stack Stopped",
+        };
+
+        write!(f, "{message}")
+    }
+}
+
+impl core::error::Error for RecvTimeoutError {}
+
+impl From<RecvError> for RecvTimeoutError {
+    fn from(e: RecvError) -> Self {
+        match e {
+            RecvError::Truncated => RecvTimeoutError::Truncated,
+            RecvError::Stopped => RecvTimeoutError::Stopped,
+        }
+    }
+}
+
+/// Error returned by [`UdpSocket::request`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RequestError {
+    /// No reply was received, including after all retransmissions.
+    Timeout,
+    /// No route to host.
+    NoRoute,
+    /// Socket not bound to an outgoing port.
+    SocketNotBound,
+    /// There is not enough transmit buffer capacity to ever send the request.
+    PacketTooLarge,
+    /// Provided buffer was smaller than the reply.
+    Truncated,
+    /// The stack was stopped.
+    Stopped,
+}
+
+impl core::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            Self::Timeout => "Request Timed Out",
+            Self::NoRoute => "No Route To Host",
+            Self::SocketNotBound => "Socket Not Bound",
+            Self::PacketTooLarge => "Packet Larger Than Transmit Buffer",
+            Self::Truncated => "Buffer Smaller Than Packet",
+            Self::Stopped => "Stack Stopped",
+        };
+
+        write!(f, "{message}")
+    }
+}
+
+impl core::error::Error for RequestError {}
+
+impl RequestError {
+    fn from_send(e: SendError) -> Self {
+        match e {
+            SendError::NoRoute => RequestError::NoRoute,
+            SendError::SocketNotBound => RequestError::SocketNotBound,
+            SendError::PacketTooLarge => RequestError::PacketTooLarge,
+            // `request` always passes an explicit remote endpoint.
+            SendError::NotConnected => unreachable!(),
+            SendError::Stopped => RequestError::Stopped,
+        }
+    }
+}
+
+impl From<RecvError> for RequestError {
+    fn from(e: RecvError) -> Self {
+        match e {
+            RecvError::Truncated => RequestError::Truncated,
+            RecvError::Stopped => RequestError::Stopped,
+        }
+    }
+}
+
+/// Per-socket traffic counters, see [`UdpSocket::stats`].
+///
+/// All counters wrap around on overflow.
+#[cfg(feature = "socket-stats")]
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UdpStats {
+    /// Number of datagrams handed to the application.
+    pub rx_packets: u32,
+    /// Number of datagrams queued for transmission.
+    pub tx_packets: u32,
+    /// Number of payload bytes handed to the application.
+    pub rx_bytes: u32,
+    /// Number of payload bytes queued for transmission.
+    pub tx_bytes: u32,
+    /// Number of received datagrams discarded by the socket, because they didn't fit in
+    /// the provided buffer, didn't come from the connected peer, or were rejected by the
+    /// receive filter.
+    ///
+    /// Datagrams that arrive while the receive buffer is full are not counted: smoltcp drops
+    /// them without reporting it, so they never reach the socket.
+    pub rx_discarded: u32,
+}
+
+/// Congestion event reported to the hook set with [`Stack::set_congestion_hook`].
+#[cfg(feature = "congestion-hook")]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum CongestionEvent {
+    /// A send had to wait because the socket's transmit buffer was full.
+    TxBlocked {
+        /// Local port of the socket.
+        local_port: u16,
+    },
+    /// The socket discarded a received datagram, because it didn't fit in the provided buffer,
+    /// didn't come from the expected peer, or was rejected by the receive filter.
+    ///
+    /// This is not reported for datagrams that arrive while the receive buffer is full: smoltcp
+    /// drops them without telling the socket.
+    RxDropped {
+        /// Local port of the socket.
+        local_port: u16,
+    },
+}
+
+/// How a received datagram was addressed, see [`UdpSocket::recv_from_meta`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PacketKind {
+    /// Sent to one of the interface's own addresses.
+    Unicast,
+    /// Sent to the limited broadcast address, or to the broadcast address of one of the
+    /// interface's IPv4 subnets.
+    Broadcast,
+    /// Sent to a multicast group.
+    Multicast,
+}
+
+impl PacketKind {
+    fn classify(_iface: &Interface, local_address: Option<IpAddress>) -> Self {
+        match local_address {
+            Some(addr) if addr.is_multicast() => Self::Multicast,
+            Some(addr) if addr.is_broadcast() => Self::Broadcast,
+            #[cfg(feature = "proto-ipv4")]
+            Some(IpAddress::Ipv4(addr))
+                if _iface.ip_addrs().iter().any(|cidr| match cidr {
+                    IpCidr::Ipv4(cidr) => cidr.broadcast() == Some(addr),
+                    #[allow(unreachable_patterns)]
+                    _ => false,
+                }) =>
+            {
+                Self::Broadcast
+            }
+            _ => Self::Unicast,
+        }
+    }
+}
+
+/// An IPv6 endpoint with a scope (zone) id, for sending to link-local addresses.
+///
+/// The scope id selects the interface a link-local address belongs to. An embassy-net
+/// stack has a single interface, so every scope refers to it: datagrams are always sent
+/// on the stack's interface, from its link-local address when it has one. This allows
+/// passing along scoped addresses obtained elsewhere, e.g. from service discovery.
+///
+/// ```
+/// # #[cfg(all(feature = "test-util", feature = "proto-ipv6"))]
+/// # {
+/// use embassy_futures::block_on;
+/// use embassy_futures::select::{select3, Either3};
+/// use embassy_net::loopback::LoopbackState;
+/// use embassy_net::udp::{PacketMetadata, ScopedEndpoint, UdpSocket};
+/// use embassy_net::{Config, Ipv6Address, Ipv6Cidr, StackResources, StaticConfigV6};
+///
+/// fn config(addr: Ipv6Address) -> Config {
+///     Config::ipv6_static(StaticConfigV6 {
+///         address: Ipv6Cidr::new(addr, 64),
+///         gateway: None,
+///         dns_servers: Default::default(),
+///     })
+/// }
+///
+/// let addr_a = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+/// let addr_b = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+/// let state = LoopbackState::<1500, 4>::new();
+/// let (dev_a, dev_b) = state.pair();
+/// let mut resources_a = StackResources::<2>::new();
+/// let mut resources_b = StackResources::<2>::new();
+/// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(addr_a), &mut resources_a, 1);
+/// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(addr_b), &mut resources_b, 2);
+///
+/// let (mut rx_meta_a, mut rx_a, mut tx_meta_a, mut tx_a) = ([PacketMetadata::EMPTY; 4], [0; 256], [PacketMetadata::EMPTY; 4], [0; 256]);
+/// let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+/// a.bind(1234).unwrap();
+/// let (mut rx_meta_b, mut rx_b, mut tx_meta_b, mut tx_b) = ([PacketMetadata::EMPTY; 4], [0; 256], [PacketMetadata::EMPTY; 4], [0; 256]);
+/// let mut b = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+/// b.bind(5678).unwrap();
+///
+/// let test = async {
+///     a.send_to(b"hello", ScopedEndpoint::new(addr_b, 5678, 1)).await.unwrap();
+///     let mut buf = [0; 16];
+///     let (n, meta) = b.recv_from(&mut buf).await.unwrap();
+///     assert_eq!(&buf[..n], b"hello");
+///     assert_eq!(meta.endpoint, (addr_a, 1234).into());
+/// };
+///
+/// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+///     Either3::First(()) => {}
+///     _ => unreachable!(),
+/// }
+/// assert_eq!(stack_a.device_stats().tx_frames, 1);
+/// # }
+/// ```
+#[cfg(feature = "proto-ipv6")]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScopedEndpoint {
+    /// The IPv6 address.
+    pub addr: Ipv6Address,
+    /// The port.
+    pub port: u16,
+    /// The scope id of the address.
+    pub scope: u32,
+}
+
+#[cfg(feature = "proto-ipv6")]
+impl ScopedEndpoint {
+    /// Create a new scoped endpoint.
+    pub const fn new(addr: Ipv6Address, port: u16, scope: u32) -> Self {
+        Self { addr, port, scope }
+    }
+}
+
+#[cfg(feature = "proto-ipv6")]
+impl From<ScopedEndpoint> for IpEndpoint {
+    fn from(ep: ScopedEndpoint) -> Self {
+        // There is only one interface, so the scope doesn't affect where the datagram goes.
+        IpEndpoint::new(IpAddress::Ipv6(ep.addr), ep.port)
+    }
+}
+
+#[cfg(feature = "proto-ipv6")]
+impl From<core::net::SocketAddrV6> for ScopedEndpoint {
+    fn from(addr: core::net::SocketAddrV6) -> Self {
+        Self::new(*addr.ip(), addr.port(), addr.scope_id())
+    }
 }
 
 /// An UDP socket.
+///
+/// ## Threading
+///
+/// A socket borrows its [`Stack`], whose state lives in a `RefCell` that must not be accessed
+/// from two threads or executors at once. Like the stack itself, sockets are therefore neither
+/// `Send` nor `Sync`, so they stay on the thread they were created on. Use [`split`](Self::split)
+/// to use a socket from two futures at once.
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<embassy_net::udp::UdpSocket<'static>>();
+/// ```
+///
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<embassy_net::udp::UdpSocket<'static>>();
+/// ```
+///
+/// ## Waiting to send
+///
+/// smoltcp keeps a single send waker per socket, so when several tasks wait for transmit
+/// buffer space in the async send methods, the socket queues them itself. Waiting tasks are
+/// woken one at a time, in the order they started waiting: each dispatched datagram wakes the
+/// first waiting task, and when that task is done waiting, the next one is woken if there is
+/// space left, or else stays registered for the next dispatched datagram. Tasks that find space
+/// on their first attempt don't wait at all, even if others are already waiting.
+///
+/// The queue is first come, first served for all async send methods, including
+/// [`flush`](Self::flush) and [`wait_send_capacity`](Self::wait_send_capacity). While one of
+/// them is first in the queue, the tasks behind it keep waiting until it is done, even if their
+/// datagrams would already fit.
+///
+/// Up to four tasks are queued. If more start waiting, all queued tasks are woken to retry.
+/// The async methods leave the queue when they complete or are cancelled. Callers of the
+/// `poll_*` methods are not queued: as with smoltcp's own waker, only the last of them to wait
+/// is woken, possibly spuriously.
+///
+/// ```
+/// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+/// # {
+/// use core::future::Future;
+/// use core::pin::pin;
+/// use core::sync::atomic::{AtomicUsize, Ordering};
+/// use core::task::{Context, Poll, Waker};
+/// use std::sync::Arc;
+/// use std::task::Wake;
+///
+/// use embassy_net::loopback::LoopbackState;
+/// use embassy_net::udp::{PacketMetadata, UdpSocket};
+/// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+///
+/// struct Counter(AtomicUsize);
+///
+/// impl Wake for Counter {
+///     fn wake(self: Arc<Self>) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let config = Config::ipv4_static(StaticConfigV4 {
+///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+///     gateway: None,
+///     dns_servers: Default::default(),
+/// });
+/// let state = LoopbackState::<1500, 4>::new();
+/// let mut resources = StackResources::<2>::new();
+/// let (stack, mut runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+///
+/// // A single transmit slot, so every queued datagram fills the buffer.
+/// let mut rx_meta = [PacketMetadata::EMPTY; 1];
+/// let mut rx = [0; 64];
+/// let mut tx_meta = [PacketMetadata::EMPTY; 1];
+/// let mut tx = [0; 64];
+/// let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+/// socket.bind(1234).unwrap();
+/// let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+///
+/// let counters: [_; 3] = core::array::from_fn(|_| Arc::new(Counter(AtomicUsize::new(0))));
+/// let wakers = counters.clone().map(Waker::from);
+/// let woken = || counters.each_ref().map(|c| c.0.load(Ordering::Relaxed));
+///
+/// let mut first = pin!(socket.send_to(b"0", remote));
+/// assert!(first.as_mut().poll(&mut Context::from_waker(&wakers[0])).is_ready());
+///
+/// let mut sends = [
+///     Box::pin(socket.send_to(b"1", remote)),
+///     Box::pin(socket.send_to(b"2", remote)),
+///     Box::pin(socket.send_to(b"3", remote)),
+/// ];
+/// for (send, waker) in sends.iter_mut().zip(&wakers) {
+///     assert!(send.as_mut().poll(&mut Context::from_waker(waker)).is_pending());
+/// }
+///
+/// // Dispatching the queued datagram frees the slot and wakes the first waiting task only.
+/// let mut run = pin!(runner.run());
+/// let _ = run.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+/// assert_eq!(woken(), [1, 0, 0]);
+///
+/// // Its datagram fills the slot again, so the next task stays waiting for the next dispatch.
+/// assert_eq!(sends[0].as_mut().poll(&mut Context::from_waker(&wakers[0])), Poll::Ready(Ok(())));
+/// assert_eq!(woken(), [1, 0, 0]);
+/// let _ = run.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+/// assert_eq!(woken(), [1, 1, 0]);
+/// # }
+/// ```
+///
+/// Neither a `poll_*` caller that stopped polling, nor a send that moved to another waker while
+/// waiting, keeps the tasks behind it from being woken:
+///
+/// ```
+/// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+/// # {
+/// use core::future::Future;
+/// use core::pin::pin;
+/// use core::sync::atomic::{AtomicUsize, Ordering};
+/// use core::task::{Context, Poll, Waker};
+/// use std::sync::Arc;
+/// use std::task::Wake;
+///
+/// use embassy_net::loopback::LoopbackState;
+/// use embassy_net::udp::{PacketMetadata, UdpSocket};
+/// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+///
+/// struct Counter(AtomicUsize);
+///
+/// impl Wake for Counter {
+///     fn wake(self: Arc<Self>) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let config = Config::ipv4_static(StaticConfigV4 {
+///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+///     gateway: None,
+///     dns_servers: Default::default(),
+/// });
+/// let state = LoopbackState::<1500, 4>::new();
+/// let mut resources = StackResources::<2>::new();
+/// let (stack, mut runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+///
+/// let mut rx_meta = [PacketMetadata::EMPTY; 1];
+/// let mut rx = [0; 64];
+/// let mut tx_meta = [PacketMetadata::EMPTY; 1];
+/// let mut tx = [0; 64];
+/// let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+/// socket.bind(1234).unwrap();
+/// let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+///
+/// let counters: [_; 4] = core::array::from_fn(|_| Arc::new(Counter(AtomicUsize::new(0))));
+/// let wakers = counters.clone().map(Waker::from);
+/// let woken = || counters.each_ref().map(|c| c.0.load(Ordering::Relaxed));
+/// let mut run = pin!(runner.run());
+/// let mut dispatch = || {
+///     let _ = run.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+/// };
+///
+/// // A `poll_*` caller waits, then gives up without polling again.
+/// assert!(pin!(socket.send_to(b"0", remote)).poll(&mut Context::from_waker(Waker::noop())).is_ready());
+/// let mut cx = Context::from_waker(&wakers[0]);
+/// assert!(socket.poll_send_to(b"1", remote, &mut cx).is_pending());
+///
+/// // A send waits, then moves to another waker, as under a `select` or after task migration.
+/// let mut send = pin!(socket.send_to(b"2", remote));
+/// assert!(send.as_mut().poll(&mut Context::from_waker(&wakers[1])).is_pending());
+/// assert!(send.as_mut().poll(&mut Context::from_waker(&wakers[2])).is_pending());
+///
+/// // The dispatch wakes the send's current waker.
+/// dispatch();
+/// assert_eq!(woken()[1..], [0, 1, 0]);
+/// assert_eq!(send.as_mut().poll(&mut Context::from_waker(&wakers[2])), Poll::Ready(Ok(())));
+///
+/// // Neither its old waker nor the `poll_*` caller hold up the next send.
+/// let mut next = pin!(socket.send_to(b"3", remote));
+/// assert!(next.as_mut().poll(&mut Context::from_waker(&wakers[3])).is_pending());
+/// dispatch();
+/// assert_eq!(woken()[1..], [0, 1, 1]);
+/// assert_eq!(next.as_mut().poll(&mut Context::from_waker(&wakers[3])), Poll::Ready(Ok(())));
+/// # }
+/// ```
 pub struct UdpSocket<'a> {
     stack: Stack<'a>,
     handle: SocketHandle,
+    remote: Option<IpEndpoint>,
+    recv_filter: Option<fn(IpEndpoint) -> bool>,
+    send_queue: RefCell<SendQueue>,
+    #[cfg(feature = "multicast")]
+    multicast_groups: Vec<IpAddress, IFACE_MAX_MULTICAST_GROUP_COUNT>,
+    #[cfg(feature = "socket-stats")]
+    stats: Cell<UdpStats>,
+    #[cfg(feature = "congestion-hook")]
+    pending_tx_blocked: Cell<bool>,
+    #[cfg(feature = "congestion-hook")]
+    pending_rx_dropped: Cell<u32>,
 }
 
 impl<'a> UdpSocket<'a> {
     /// Create a new UDP socket using the provided stack and buffers.
+    ///
+    /// Each metadata buffer holds one entry per queued datagram, and each payload buffer
+    /// holds the queued datagrams' data. A payload buffer must be at least as large as the
+    /// largest datagram to send or receive. A socket with empty receive buffers can only send,
+    /// and one with empty transmit buffers can only receive.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// let config = Config::ipv4_static(StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway: None,
+    ///     dns_servers: Default::default(),
+    /// });
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<2>::new();
+    /// let (stack, _runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+    ///
+    /// // A send-only socket.
+    /// let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    /// let mut tx = [0; 256];
+    /// let mut socket = UdpSocket::new(stack, &mut [], &mut [], &mut tx_meta, &mut tx);
+    /// socket.bind(1234).unwrap();
+    /// block_on(socket.send_to(b"hello", (Ipv4Address::new(10, 0, 0, 2), 5678))).unwrap();
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if all sockets of the stack are in use, see [`try_new`](Self::try_new).
     pub fn new(
         stack: Stack<'a>,
         rx_meta: &'a mut [PacketMetadata],
@@ -67,10 +793,256 @@ impl<'a> UdpSocket<'a> {
             ))
         });
 
-        Self { stack, handle }
+        Self {
+            stack,
+            handle,
+            remote: None,
+            recv_filter: None,
+            send_queue: RefCell::new(SendQueue::new()),
+            #[cfg(feature = "multicast")]
+            multicast_groups: Vec::new(),
+            #[cfg(feature = "socket-stats")]
+            stats: Cell::new(UdpStats::default()),
+            #[cfg(feature = "congestion-hook")]
+            pending_tx_blocked: Cell::new(false),
+            #[cfg(feature = "congestion-hook")]
+            pending_rx_dropped: Cell::new(0),
+        }
+    }
+
+    /// Create a new UDP socket using the provided stack and buffers.
+    ///
+    /// Unlike [`new`](Self::new), this returns `Err(NewSocketError::NoFreeSocket)` instead of
+    /// panicking if all sockets of the stack are in use. See [`Stack::socket_capacity`].
+    ///
+    /// Returns `Err(NewSocketError::EmptyBuffer)` if any of the buffers is empty.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{NewSocketError, PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, StackResources};
+    ///
+    /// fn meta() -> &'static mut [PacketMetadata] {
+    ///     Box::leak(Box::new([PacketMetadata::EMPTY; 4]))
+    /// }
+    /// fn buf() -> &'static mut [u8] {
+    ///     Box::leak(Box::new([0; 256]))
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<3>::new();
+    /// let (stack, _runner) = embassy_net::new(state.device(), Config::default(), &mut resources, 1);
+    ///
+    /// // The stack may use some of the sockets itself, e.g. for DNS.
+    /// let mut sockets = Vec::new();
+    /// let err = loop {
+    ///     match UdpSocket::try_new(stack, meta(), buf(), meta(), buf()) {
+    ///         Ok(socket) => sockets.push(socket),
+    ///         Err(e) => break e,
+    ///     }
+    /// };
+    /// assert_eq!(err, NewSocketError::NoFreeSocket);
+    /// assert!(!sockets.is_empty() && sockets.len() <= stack.socket_capacity());
+    ///
+    /// // Dropping a socket frees its slot.
+    /// sockets.pop();
+    ///
+    /// // A socket with an empty buffer could never send or receive.
+    /// let err = UdpSocket::try_new(stack, meta(), buf(), meta(), &mut []).err();
+    /// assert_eq!(err, Some(NewSocketError::EmptyBuffer));
+    /// let err = UdpSocket::try_new(stack, &mut [], buf(), meta(), buf()).err();
+    /// assert_eq!(err, Some(NewSocketError::EmptyBuffer));
+    ///
+    /// assert!(UdpSocket::try_new(stack, meta(), buf(), meta(), buf()).is_ok());
+    /// # }
+    /// ```
+    pub fn try_new(
+        stack: Stack<'a>,
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self, NewSocketError> {
+        if rx_meta.is_empty() || rx_buffer.is_empty() || tx_meta.is_empty() || tx_buffer.is_empty() {
+            return Err(NewSocketError::EmptyBuffer);
+        }
+        if !stack.with(|i| i.has_free_socket()) {
+            return Err(NewSocketError::NoFreeSocket);
+        }
+        Ok(Self::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer))
+    }
+
+    /// Create a new UDP socket using buffers borrowed from `pool`.
+    ///
+    /// The buffers are returned to the pool when the socket is dropped, so the pool only
+    /// needs as many buffers as there are sockets alive at the same time.
+    ///
+    /// Returns `Err(NewSocketError::PoolExhausted)` if all buffers of the pool are lent out,
+    /// and `Err(NewSocketError::NoFreeSocket)` if all sockets of the stack are in use.
+    pub fn new_pooled<
+        const N: usize,
+        const RX_META: usize,
+        const RX_SZ: usize,
+        const TX_META: usize,
+        const TX_SZ: usize,
+    >(
+        stack: Stack<'a>,
+        pool: &'a UdpBufferPool<N, RX_META, RX_SZ, TX_META, TX_SZ>,
+    ) -> Result<PooledUdpSocket<'a, N, RX_META, RX_SZ, TX_META, TX_SZ>, NewSocketError> {
+        let mut bufs = pool.pool.alloc().ok_or(NewSocketError::PoolExhausted)?;
+        let p = bufs.as_ptr();
+        // Only the metadata needs initializing, the payload bytes are always written before being read.
+        unsafe {
+            core::ptr::addr_of_mut!((*p).rx_meta).write([PacketMetadata::EMPTY; RX_META]);
+            core::ptr::addr_of_mut!((*p).tx_meta).write([PacketMetadata::EMPTY; TX_META]);
+        }
+        let b = unsafe { bufs.as_mut() };
+        match UdpSocket::try_new(stack, &mut b.rx_meta, &mut b.rx, &mut b.tx_meta, &mut b.tx) {
+            Ok(socket) => Ok(PooledUdpSocket {
+                socket: ManuallyDrop::new(socket),
+                pool,
+                bufs,
+            }),
+            Err(e) => {
+                unsafe { pool.pool.free(bufs) };
+                Err(e)
+            }
+        }
     }
 
     /// Bind the socket to a local endpoint.
+    ///
+    /// If the endpoint has no address, the socket receives datagrams sent to the port on any
+    /// local address. This includes both IPv4 and IPv6 addresses when the interface has both,
+    /// so a single socket serves both families, and replies to either can be sent from it.
+    /// If it has one, the socket only receives unicast datagrams sent to that
+    /// address, which allows listening on a single address of a multi-homed interface.
+    /// Broadcast and multicast datagrams to the port are received either way. Datagrams sent
+    /// from a socket bound to an address use it as their source address.
+    ///
+    /// If the port is 0, a dynamic local port not used by any other socket is allocated. If
+    /// the dynamic range is too small to leave a port free, this returns
+    /// `Err(BindError::NoFreePort)`.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{BindError, PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let config = Config::ipv4_static(StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway: None,
+    ///     dns_servers: Default::default(),
+    /// });
+    /// let mut resources = StackResources::<3>::new();
+    /// let (stack, _runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+    /// stack.set_ephemeral_port_range(5000, 5000).unwrap();
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 1];
+    /// let mut rx_a = [0; 64];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 1];
+    /// let mut tx_a = [0; 64];
+    /// let mut a = UdpSocket::new(stack, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// a.bind(0).unwrap();
+    /// assert_eq!(a.endpoint().port, 5000);
+    ///
+    /// // The only dynamic port is taken.
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 1];
+    /// let mut rx_b = [0; 64];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 1];
+    /// let mut tx_b = [0; 64];
+    /// let mut b = UdpSocket::new(stack, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// assert_eq!(b.bind(0), Err(BindError::NoFreePort));
+    ///
+    /// // Closing the first socket frees it.
+    /// a.close();
+    /// b.bind(0).unwrap();
+    /// assert_eq!(b.endpoint().port, 5000);
+    /// # }
+    /// ```
+    ///
+    /// Binding does not check whether another socket already uses the endpoint, so a new
+    /// socket can be bound while an old one still holds the port. When several sockets match
+    /// an incoming datagram, only the first one in the stack's socket set receives it, which is
+    /// usually the one created first.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4", feature = "proto-ipv6"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+    /// use embassy_net::{
+    ///     Config, ConfigV4, ConfigV6, IpAddress, Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr, StackResources,
+    ///     StaticConfigV4, StaticConfigV6,
+    /// };
+    ///
+    /// fn config(v4: Ipv4Address, v6: Ipv6Address) -> Config {
+    ///     let mut config = Config::default();
+    ///     config.ipv4 = ConfigV4::Static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(v4, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     });
+    ///     config.ipv6 = ConfigV6::Static(StaticConfigV6 {
+    ///         address: Ipv6Cidr::new(v6, 64),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     });
+    ///     config
+    /// }
+    ///
+    /// let v4_b = Ipv4Address::new(10, 0, 0, 2);
+    /// let v6_b = Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let config_a = config(Ipv4Address::new(10, 0, 0, 1), Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 1));
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config_a, &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(v4_b, v6_b), &mut resources_b, 2);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 256];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 256];
+    /// let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// a.bind(1234).unwrap();
+    ///
+    /// // One socket, bound to all addresses of both families.
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 256];
+    /// let mut b = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// b.bind(5678).unwrap();
+    ///
+    /// let test = async {
+    ///     let mut buf = [0; 16];
+    ///     for addr in [IpAddress::Ipv4(v4_b), IpAddress::Ipv6(v6_b)] {
+    ///         a.send_to(b"ping", (addr, 5678)).await.unwrap();
+    ///         let (n, meta) = b.recv_from(&mut buf).await.unwrap();
+    ///         assert_eq!(&buf[..n], b"ping");
+    ///         assert_eq!(meta.local_address, Some(addr));
+    ///
+    ///         b.send_to(b"pong", meta.endpoint).await.unwrap();
+    ///         let (n, meta) = a.recv_from(&mut buf).await.unwrap();
+    ///         assert_eq!(&buf[..n], b"pong");
+    ///         assert_eq!(meta.endpoint, (addr, 5678).into());
+    ///     }
+    /// };
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
     pub fn bind<T>(&mut self, endpoint: T) -> Result<(), BindError>
     where
         T: Into<IpListenEndpoint>,
@@ -79,7 +1051,10 @@ impl<'a> UdpSocket<'a> {
 
         if endpoint.port == 0 {
             // If user didn't specify port allocate a dynamic port.
-            endpoint.port = self.stack.with_mut(|i| i.get_local_port());
+            endpoint.port = match self.stack.with_mut(|i| i.get_local_port()) {
+                Some(port) => port,
+                None => return Err(BindError::NoFreePort),
+            };
         }
 
         match self.with_mut(|s, _| s.bind(endpoint)) {
@@ -89,64 +1064,590 @@ impl<'a> UdpSocket<'a> {
         }
     }
 
-    fn with<R>(&self, f: impl FnOnce(&udp::Socket, &Interface) -> R) -> R {
-        self.stack.with(|i| {
-            let socket = i.sockets.get::<udp::Socket>(self.handle);
-            f(socket, &i.iface)
-        })
+    /// Bind the socket to a new local endpoint, closing the current binding first.
+    ///
+    /// The socket keeps its buffers, but datagrams still queued for the old
+    /// binding are discarded. The default remote endpoint set by
+    /// [`connect`](Self::connect) is kept.
+    pub fn rebind<T>(&mut self, endpoint: T) -> Result<(), BindError>
+    where
+        T: Into<IpListenEndpoint>,
+    {
+        if self.is_open() {
+            self.close();
+        }
+        self.bind(endpoint)
     }
 
-    fn with_mut<R>(&self, f: impl FnOnce(&mut udp::Socket, &mut Interface) -> R) -> R {
-        self.stack.with_mut(|i| {
-            let socket = i.sockets.get_mut::<udp::Socket>(self.handle);
-            let res = f(socket, &mut i.iface);
-            i.waker.wake();
-            res
-        })
+    /// Set the default remote endpoint of the socket.
+    ///
+    /// Once connected, [`send`](Self::send) sends datagrams to `remote` and [`recv`](Self::recv)
+    /// discards datagrams that were not sent from `remote`. [`send_to`](Self::send_to) and
+    /// [`recv_from`](Self::recv_from) are not affected.
+    ///
+    /// This does not bind the socket, [`bind`](Self::bind) must still be called.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, SendError, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<3>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let mut rx_meta_p = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_p = [0; 256];
+    /// let mut tx_meta_p = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_p = [0; 256];
+    /// let mut peer = UdpSocket::new(stack_a, &mut rx_meta_p, &mut rx_p, &mut tx_meta_p, &mut tx_p);
+    /// peer.bind(1111).unwrap();
+    ///
+    /// let mut rx_meta_o = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_o = [0; 256];
+    /// let mut tx_meta_o = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_o = [0; 256];
+    /// let mut other = UdpSocket::new(stack_a, &mut rx_meta_o, &mut rx_o, &mut tx_meta_o, &mut tx_o);
+    /// other.bind(2222).unwrap();
+    ///
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 256];
+    /// let mut socket = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// socket.bind(5678).unwrap();
+    /// assert_eq!(block_on(socket.send(b"hello")), Err(SendError::NotConnected));
+    /// socket.connect((Ipv4Address::new(10, 0, 0, 1), 1111));
+    ///
+    /// let test = async {
+    ///     let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    ///     other.send_to(b"spam", remote).await.unwrap();
+    ///     other.flush().await;
+    ///     peer.send_to(b"data", remote).await.unwrap();
+    ///
+    ///     // The datagram from the other socket is discarded.
+    ///     let mut buf = [0; 16];
+    ///     let n = socket.recv(&mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..n], b"data");
+    ///
+    ///     socket.send(b"reply").await.unwrap();
+    ///     let (n, meta) = peer.recv_from(&mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..n], b"reply");
+    ///     assert_eq!(meta.endpoint, remote.into());
+    /// };
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub fn connect<T>(&mut self, remote: T)
+    where
+        T: Into<IpEndpoint>,
+    {
+        self.remote = Some(remote.into());
     }
 
-    /// Wait until the socket becomes readable.
-    ///
-    /// A socket is readable when a packet has been received, or when there are queued packets in
-    /// the buffer.
-    pub fn wait_recv_ready(&self) -> impl Future<Output = ()> + '_ {
-        poll_fn(move |cx| self.poll_recv_ready(cx))
+    /// Clear the default remote endpoint set by [`connect`](Self::connect).
+    pub fn disconnect(&mut self) {
+        self.remote = None;
     }
 
-    /// Wait until a datagram can be read.
+    /// Set a filter on the source endpoint of received datagrams, or remove it with `None`.
     ///
-    /// When no datagram is readable, this method will return `Poll::Pending` and
-    /// register the current task to be notified when a datagram is received.
+    /// Queued datagrams for which `filter` returns `false` are silently dropped by the receive
+    /// methods, and never returned to the application.
     ///
-    /// When a datagram is received, this method will return `Poll::Ready`.
-    pub fn poll_recv_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
-        self.with_mut(|s, _| {
-            if s.can_recv() {
-                Poll::Ready(())
-            } else {
-                // socket buffer is empty wait until at least one byte has arrived
-                s.register_recv_waker(cx.waker());
-                Poll::Pending
-            }
-        })
-    }
-
-    /// Receive a datagram.
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, IpEndpoint, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
     ///
-    /// This method will wait until a datagram is received.
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
     ///
-    /// Returns the number of bytes received and the remote endpoint.
-    pub fn recv_from<'s>(
-        &'s self,
-        buf: &'s mut [u8],
-    ) -> impl Future<Output = Result<(usize, UdpMetadata), RecvError>> + 's {
-        poll_fn(|cx| self.poll_recv_from(buf, cx))
-    }
-
-    /// Receive a datagram.
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<3>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
     ///
-    /// When no datagram is available, this method will return `Poll::Pending` and
-    /// register the current task to be notified when a datagram is received.
+    /// let mut rx_meta_t = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_t = [0; 256];
+    /// let mut tx_meta_t = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_t = [0; 256];
+    /// let mut trusted = UdpSocket::new(stack_a, &mut rx_meta_t, &mut rx_t, &mut tx_meta_t, &mut tx_t);
+    /// trusted.bind(1111).unwrap();
+    ///
+    /// let mut rx_meta_u = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_u = [0; 256];
+    /// let mut tx_meta_u = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_u = [0; 256];
+    /// let mut untrusted = UdpSocket::new(stack_a, &mut rx_meta_u, &mut rx_u, &mut tx_meta_u, &mut tx_u);
+    /// untrusted.bind(2222).unwrap();
+    ///
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 256];
+    /// let mut socket = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// socket.bind(5678).unwrap();
+    /// socket.set_recv_filter(Some(|source: IpEndpoint| source.port == 1111));
+    ///
+    /// let test = async {
+    ///     let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    ///     untrusted.send_to(b"rejected", remote).await.unwrap();
+    ///     untrusted.flush().await;
+    ///     trusted.send_to(b"accepted", remote).await.unwrap();
+    ///
+    ///     let mut buf = [0; 16];
+    ///     let (n, meta) = socket.recv_from(&mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..n], b"accepted");
+    ///     assert_eq!(meta.endpoint.port, 1111);
+    /// };
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub fn set_recv_filter(&mut self, filter: Option<fn(IpEndpoint) -> bool>) {
+        self.recv_filter = filter;
+    }
+
+    /// Returns the default remote endpoint set by [`connect`](Self::connect), if any.
+    pub fn remote_endpoint(&self) -> Option<IpEndpoint> {
+        self.remote
+    }
+
+    /// Returns the traffic counters of this socket.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4", feature = "socket-stats"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, RecvError, UdpSocket, UdpStats};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use embassy_time::Timer;
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 256];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 256];
+    /// let mut tx = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// tx.bind(1234).unwrap();
+    ///
+    /// // The receive buffer only holds one datagram.
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 1];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 1];
+    /// let mut tx_b = [0; 256];
+    /// let mut rx = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// rx.bind(5678).unwrap();
+    ///
+    /// let test = async {
+    ///     let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    ///     tx.send_to(b"hello", remote).await.unwrap();
+    ///     tx.send_to(b"hello", remote).await.unwrap();
+    ///     tx.send_to(b"hi", remote).await.unwrap();
+    ///     Timer::after_millis(50).await;
+    ///
+    ///     // The first datagram doesn't fit and is discarded. The other two arrived while the
+    ///     // receive buffer was full, so smoltcp dropped them before they reached the socket.
+    ///     let mut buf = [0; 2];
+    ///     assert_eq!(rx.recv_from(&mut buf).await, Err(RecvError::Truncated));
+    ///     let stats = rx.stats();
+    ///     assert_eq!(stats.rx_discarded, 1);
+    ///     assert_eq!(stats.rx_packets, 0);
+    ///
+    ///     tx.send_to(b"hi", remote).await.unwrap();
+    ///     let (n, _) = rx.recv_from(&mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..n], b"hi");
+    ///     assert_eq!(
+    ///         rx.stats(),
+    ///         UdpStats {
+    ///             rx_packets: 1,
+    ///             rx_bytes: 2,
+    ///             rx_discarded: 1,
+    ///             ..Default::default()
+    ///         }
+    ///     );
+    /// };
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "socket-stats")]
+    pub fn stats(&self) -> UdpStats {
+        self.stats.get()
+    }
+
+    /// Reset the traffic counters of this socket to zero.
+    #[cfg(feature = "socket-stats")]
+    pub fn reset_stats(&self) {
+        self.stats.set(UdpStats::default())
+    }
+
+    /// Log the state of the socket at info level.
+    ///
+    /// This logs the local endpoint, whether the socket is open, the occupancy of both buffers
+    /// and, with the `socket-stats` feature, the traffic counters, in one go for diagnostics.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4", feature = "log"))]
+    /// # {
+    /// use std::sync::Mutex;
+    ///
+    /// use embassy_futures::block_on;
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    ///
+    /// struct Capture;
+    ///
+    /// impl log::Log for Capture {
+    ///     fn enabled(&self, _: &log::Metadata) -> bool {
+    ///         true
+    ///     }
+    ///     fn log(&self, record: &log::Record) {
+    ///         LINES.lock().unwrap().push(record.args().to_string());
+    ///     }
+    ///     fn flush(&self) {}
+    /// }
+    ///
+    /// log::set_logger(&Capture).unwrap();
+    /// log::set_max_level(log::LevelFilter::Info);
+    ///
+    /// let config = Config::ipv4_static(StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway: None,
+    ///     dns_servers: Default::default(),
+    /// });
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<2>::new();
+    /// let (stack, _runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+    ///
+    /// let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    /// let mut rx = [0; 128];
+    /// let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    /// let mut tx = [0; 256];
+    /// let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+    /// socket.bind(1234).unwrap();
+    /// block_on(socket.send_to(b"queued", (Ipv4Address::new(10, 0, 0, 2), 5678))).unwrap();
+    ///
+    /// LINES.lock().unwrap().clear();
+    /// socket.debug_dump();
+    /// let lines = LINES.lock().unwrap();
+    /// assert!(lines[0].contains("port: 1234"), "{}", lines[0]);
+    /// assert!(lines[0].contains("open=true"), "{}", lines[0]);
+    /// assert!(lines[0].contains("rx=0/128 bytes tx=6/256 bytes"), "{}", lines[0]);
+    /// #[cfg(feature = "socket-stats")]
+    /// assert!(lines[1].contains("tx=1 packets 6 bytes"), "{}", lines[1]);
+    /// # }
+    /// ```
+    #[cfg(any(feature = "defmt", feature = "log"))]
+    pub fn debug_dump(&self) {
+        self.with(|s, _| {
+            info!(
+                "udp socket {:?}: open={:?} remote={:?} rx={:?}/{:?} bytes tx={:?}/{:?} bytes",
+                s.endpoint(),
+                s.is_open(),
+                self.remote,
+                s.recv_queue(),
+                s.payload_recv_capacity(),
+                s.send_queue(),
+                s.payload_send_capacity(),
+            );
+        });
+        #[cfg(feature = "socket-stats")]
+        {
+            let stats = self.stats.get();
+            info!(
+                "udp socket stats: rx={:?} packets {:?} bytes, tx={:?} packets {:?} bytes, rx discarded={:?}",
+                stats.rx_packets, stats.rx_bytes, stats.tx_packets, stats.tx_bytes, stats.rx_discarded,
+            );
+        }
+    }
+
+    #[inline]
+    fn record_rx(&self, _len: usize) {
+        #[cfg(feature = "socket-stats")]
+        {
+            let mut stats = self.stats.get();
+            stats.rx_packets = stats.rx_packets.wrapping_add(1);
+            stats.rx_bytes = stats.rx_bytes.wrapping_add(_len as u32);
+            self.stats.set(stats);
+        }
+    }
+
+    #[inline]
+    fn record_rx_discarded(&self) {
+        #[cfg(feature = "socket-stats")]
+        {
+            let mut stats = self.stats.get();
+            stats.rx_discarded = stats.rx_discarded.wrapping_add(1);
+            self.stats.set(stats);
+        }
+        #[cfg(feature = "congestion-hook")]
+        self.pending_rx_dropped
+            .set(self.pending_rx_dropped.get().saturating_add(1));
+    }
+
+    /// Drop queued datagrams rejected by the receive filter, up to the first accepted one.
+    fn drop_filtered(&self, s: &mut udp::Socket) {
+        let Some(filter) = self.recv_filter else {
+            return;
+        };
+        while let Ok((_, meta)) = s.peek() {
+            if filter(meta.endpoint) {
+                break;
+            }
+            let _ = s.recv();
+            self.record_rx_discarded();
+        }
+    }
+
+    #[inline]
+    fn record_tx_blocked(&self) {
+        #[cfg(feature = "congestion-hook")]
+        self.pending_tx_blocked.set(true);
+    }
+
+    /// Report congestion events recorded during the last access to the socket.
+    ///
+    /// This must be called with the stack not borrowed, so the hook can use it.
+    #[cfg(feature = "congestion-hook")]
+    fn report_congestion(&self) {
+        let tx_blocked = self.pending_tx_blocked.replace(false);
+        let rx_dropped = self.pending_rx_dropped.replace(0);
+        if !tx_blocked && rx_dropped == 0 {
+            return;
+        }
+        let Some(hook) = self.stack.with(|i| i.congestion_hook) else {
+            return;
+        };
+
+        let local_port = self.with(|s, _| s.endpoint().port);
+        if tx_blocked {
+            hook(CongestionEvent::TxBlocked { local_port });
+        }
+        for _ in 0..rx_dropped {
+            hook(CongestionEvent::RxDropped { local_port });
+        }
+    }
+
+    #[inline]
+    fn record_tx(&self, _len: usize) {
+        #[cfg(feature = "socket-stats")]
+        {
+            let mut stats = self.stats.get();
+            stats.tx_packets = stats.tx_packets.wrapping_add(1);
+            stats.tx_bytes = stats.tx_bytes.wrapping_add(_len as u32);
+            self.stats.set(stats);
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&udp::Socket, &Interface) -> R) -> R {
+        self.stack.with(|i| {
+            let socket = i.sockets.get::<udp::Socket>(self.handle);
+            f(socket, &i.iface)
+        })
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(&mut udp::Socket, &mut Interface) -> R) -> R {
+        let res = self.stack.with_mut(|i| {
+            let socket = i.sockets.get_mut::<udp::Socket>(self.handle);
+            let res = f(socket, &mut i.iface);
+            i.waker.wake();
+            res
+        });
+        #[cfg(feature = "congestion-hook")]
+        self.report_congestion();
+        res
+    }
+
+    /// Whether the stack was stopped by [`Runner::run_until_stopped`](crate::Runner::run_until_stopped).
+    fn stopped(&self) -> bool {
+        self.stack.with(|i| i.stopped)
+    }
+
+    /// Queue the current task to be woken when transmit buffer space is freed.
+    fn wait_send(&self, s: &mut udp::Socket, waker: &Waker) {
+        let q = &mut *self.send_queue.borrow_mut();
+        let Some(id) = q.current else {
+            // Not called from an async send method, there is nothing that would leave the queue.
+            if q.waiters.is_empty() {
+                s.register_send_waker(waker);
+            }
+            q.poller = Some(waker.clone());
+            return;
+        };
+
+        match q.waiters.iter().position(|(i, _)| *i == id) {
+            Some(pos) => {
+                if !q.waiters[pos].1.will_wake(waker) {
+                    q.waiters[pos].1 = waker.clone();
+                }
+                // The first task is polled again after a dispatch, pass it on to the poller.
+                if pos == 0 {
+                    if let Some(poller) = q.poller.take() {
+                        if !poller.will_wake(waker) {
+                            poller.wake();
+                        }
+                    }
+                }
+            }
+            None => {
+                if q.waiters.is_full() {
+                    for (_, w) in mem::take(&mut q.waiters) {
+                        w.wake();
+                    }
+                }
+                // NOTE(unwrap): the queue was emptied above if it was full.
+                unwrap!(q.waiters.push((id, waker.clone())).ok());
+            }
+        }
+        // smoltcp only holds one waker, give it to the task that has waited the longest.
+        s.register_send_waker(&q.waiters[0].1);
+    }
+
+    /// Remove a send operation from the send queue, passing its turn on to the next one.
+    fn stop_waiting_send(&self, id: u32) {
+        let mut q = self.send_queue.borrow_mut();
+        let Some(pos) = q.waiters.iter().position(|(i, _)| *i == id) else {
+            return;
+        };
+        q.waiters.remove(pos);
+        if pos != 0 {
+            return;
+        }
+
+        if let Some(poller) = q.poller.take() {
+            poller.wake();
+        }
+        if let Some((_, next)) = q.waiters.first() {
+            self.with_mut(|s, _| {
+                if s.can_send() {
+                    next.wake_by_ref();
+                } else {
+                    s.register_send_waker(next);
+                }
+            });
+        }
+    }
+
+    /// Drive a send operation, leaving the send queue once it completes or is dropped.
+    async fn send_waiting<R>(&self, mut poll: impl FnMut(&mut Context<'_>) -> Poll<R>) -> R {
+        let waiter = SendWaiter {
+            socket: self,
+            id: self.send_queue.borrow_mut().next_id(),
+        };
+        poll_fn(|cx| {
+            self.send_queue.borrow_mut().current = Some(waiter.id);
+            let res = poll(cx);
+            self.send_queue.borrow_mut().current = None;
+            if res.is_ready() {
+                self.stop_waiting_send(waiter.id);
+            }
+            res
+        })
+        .await
+    }
+
+    /// Wait until the socket becomes readable.
+    ///
+    /// A socket is readable when a packet has been received, or when there are queued packets in
+    /// the buffer.
+    pub fn wait_recv_ready(&self) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| self.poll_recv_ready(cx))
+    }
+
+    /// Wait until a datagram can be read.
+    ///
+    /// When no datagram is readable, this method will return `Poll::Pending` and
+    /// register the current task to be notified when a datagram is received.
+    ///
+    /// When a datagram is received, or the stack was stopped, this method will return `Poll::Ready`.
+    pub fn poll_recv_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let stopped = self.stopped();
+        self.with_mut(|s, _| {
+            self.drop_filtered(s);
+            if s.can_recv() || stopped {
+                Poll::Ready(())
+            } else {
+                // socket buffer is empty wait until at least one byte has arrived
+                s.register_recv_waker(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Receive a datagram.
+    ///
+    /// This method will wait until a datagram is received.
+    ///
+    /// Returns the number of bytes received and the datagram metadata. Besides the
+    /// remote endpoint, the metadata carries the local address the datagram was sent
+    /// to in [`UdpMetadata::local_address`], so a socket bound to a wildcard address
+    /// can reply from the same address. The hop limit of received datagrams is not
+    /// exposed by smoltcp.
+    pub fn recv_from<'s>(
+        &'s self,
+        buf: &'s mut [u8],
+    ) -> impl Future<Output = Result<(usize, UdpMetadata), RecvError>> + 's {
+        poll_fn(|cx| self.poll_recv_from(buf, cx))
+    }
+
+    /// Receive a datagram.
+    ///
+    /// When no datagram is available, this method will return `Poll::Pending` and
+    /// register the current task to be notified when a datagram is received.
     ///
     /// When a datagram is received, this method will return `Poll::Ready` with the
     /// number of bytes received and the remote endpoint.
@@ -155,242 +1656,2372 @@ impl<'a> UdpSocket<'a> {
         buf: &mut [u8],
         cx: &mut Context<'_>,
     ) -> Poll<Result<(usize, UdpMetadata), RecvError>> {
-        self.with_mut(|s, _| match s.recv_slice(buf) {
-            Ok((n, meta)) => Poll::Ready(Ok((n, meta))),
-            // No data ready
-            Err(udp::RecvError::Truncated) => Poll::Ready(Err(RecvError::Truncated)),
-            Err(udp::RecvError::Exhausted) => {
+        if self.stopped() {
+            return Poll::Ready(Err(RecvError::Stopped));
+        }
+
+        self.with_mut(|s, _| {
+            self.drop_filtered(s);
+            match s.recv_slice(buf) {
+                Ok((n, meta)) => {
+                    self.record_rx(n);
+                    Poll::Ready(Ok((n, meta)))
+                }
+                Err(udp::RecvError::Truncated) => {
+                    self.record_rx_discarded();
+                    Poll::Ready(Err(RecvError::Truncated))
+                }
+                // No data ready
+                Err(udp::RecvError::Exhausted) => {
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
+    /// Receive a datagram, along with how it was addressed.
+    ///
+    /// Like [`recv_from`](Self::recv_from), but also returns whether the datagram was sent to
+    /// this host directly, or to a broadcast or multicast address. This is useful for servers
+    /// that must reply to broadcast or multicast requests with a unicast datagram.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketKind, PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 256];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 256];
+    /// let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// a.bind(1234).unwrap();
+    ///
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 256];
+    /// let mut b = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// b.bind(5678).unwrap();
+    ///
+    /// let test = async {
+    ///     let mut buf = [0; 16];
+    ///     a.send_to(b"unicast", (Ipv4Address::new(10, 0, 0, 2), 5678)).await.unwrap();
+    ///     let (n, _, kind) = b.recv_from_meta(&mut buf).await.unwrap();
+    ///     assert_eq!((&buf[..n], kind), (&b"unicast"[..], PacketKind::Unicast));
+    ///
+    ///     a.send_broadcast(b"broadcast", 5678).await.unwrap();
+    ///     let (n, _, kind) = b.recv_from_meta(&mut buf).await.unwrap();
+    ///     assert_eq!((&buf[..n], kind), (&b"broadcast"[..], PacketKind::Broadcast));
+    ///
+    ///     // So is a broadcast to the subnet.
+    ///     a.send_to(b"subnet", (Ipv4Address::new(10, 0, 0, 255), 5678)).await.unwrap();
+    ///     let (n, _, kind) = b.recv_from_meta(&mut buf).await.unwrap();
+    ///     assert_eq!((&buf[..n], kind), (&b"subnet"[..], PacketKind::Broadcast));
+    /// };
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub fn recv_from_meta<'s>(
+        &'s self,
+        buf: &'s mut [u8],
+    ) -> impl Future<Output = Result<(usize, UdpMetadata, PacketKind), RecvError>> + 's {
+        poll_fn(|cx| self.poll_recv_from_meta(buf, cx))
+    }
+
+    /// Receive a datagram, along with how it was addressed.
+    ///
+    /// See [`poll_recv_from`](Self::poll_recv_from) and [`recv_from_meta`](Self::recv_from_meta).
+    pub fn poll_recv_from_meta(
+        &self,
+        buf: &mut [u8],
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(usize, UdpMetadata, PacketKind), RecvError>> {
+        self.poll_recv_from(buf, cx).map(|res| {
+            res.map(|(n, meta)| {
+                let kind = self.with(|_, i| PacketKind::classify(i, meta.local_address));
+                (n, meta, kind)
+            })
+        })
+    }
+
+    /// Receive multiple datagrams.
+    ///
+    /// This method will wait until at least one datagram is received, then receives as many
+    /// queued datagrams as possible in one go, one per buffer in `bufs`. The size and metadata
+    /// of each datagram is written to the matching entry of `meta`.
+    ///
+    /// Returns the number of datagrams received, which is at most the length of the shorter of
+    /// `bufs` and `meta`.
+    ///
+    /// If the first queued datagram does not fit in `bufs[0]`, it is discarded and
+    /// `Err(RecvError::Truncated)` is returned. A later datagram that does not fit its buffer
+    /// is left in the queue for the next call.
+    pub async fn recv_from_many(
+        &self,
+        bufs: &mut [&mut [u8]],
+        meta: &mut [(usize, UdpMetadata)],
+    ) -> Result<usize, RecvError> {
+        poll_fn(|cx| self.poll_recv_from_many(bufs, meta, cx)).await
+    }
+
+    /// Receive multiple datagrams.
+    ///
+    /// When no datagram is available, this method will return `Poll::Pending` and
+    /// register the current task to be notified when a datagram is received.
+    ///
+    /// See [`recv_from_many`](Self::recv_from_many) for details.
+    pub fn poll_recv_from_many(
+        &self,
+        bufs: &mut [&mut [u8]],
+        meta: &mut [(usize, UdpMetadata)],
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<usize, RecvError>> {
+        if self.stopped() {
+            return Poll::Ready(Err(RecvError::Stopped));
+        }
+        let max = bufs.len().min(meta.len());
+        if max == 0 {
+            return Poll::Ready(Ok(0));
+        }
+
+        self.with_mut(|s, _| {
+            let mut count = 0;
+            while count < max {
+                self.drop_filtered(s);
+                let size = match s.peek() {
+                    Ok((payload, _)) => payload.len(),
+                    Err(_) => break,
+                };
+
+                if size > bufs[count].len() {
+                    if count == 0 {
+                        let _ = s.recv();
+                        self.record_rx_discarded();
+                        return Poll::Ready(Err(RecvError::Truncated));
+                    }
+                    break;
+                }
+
+                match s.recv_slice(bufs[count]) {
+                    Ok((n, m)) => {
+                        self.record_rx(n);
+                        meta[count] = (n, m);
+                        count += 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            if count == 0 {
+                // No data ready
                 s.register_recv_waker(cx.waker());
                 Poll::Pending
+            } else {
+                Poll::Ready(Ok(count))
             }
         })
     }
 
-    /// Receive a datagram with a zero-copy function.
+    /// Receive a datagram from the connected remote endpoint.
+    ///
+    /// This method will wait until a datagram is received.
+    ///
+    /// Returns the number of bytes received. If the socket is connected, datagrams from any
+    /// other endpoint are discarded. If it is not connected, datagrams from any endpoint are accepted.
+    pub fn recv<'s>(&'s self, buf: &'s mut [u8]) -> impl Future<Output = Result<usize, RecvError>> + 's {
+        poll_fn(|cx| self.poll_recv(buf, cx))
+    }
+
+    /// Receive a datagram from the connected remote endpoint.
     ///
     /// When no datagram is available, this method will return `Poll::Pending` and
     /// register the current task to be notified when a datagram is received.
     ///
-    /// When a datagram is received, this method will call the provided function
-    /// with a reference to the received bytes and the remote endpoint and return
-    /// `Poll::Ready` with the function's returned value.
+    /// When a datagram is received, this method will return `Poll::Ready` with the
+    /// number of bytes received. See [`recv`](Self::recv) for how datagrams are filtered.
+    pub fn poll_recv(&self, buf: &mut [u8], cx: &mut Context<'_>) -> Poll<Result<usize, RecvError>> {
+        self.poll_recv_from_peer(buf, self.remote, cx)
+    }
+
+    /// Receive a datagram, dropping those not sent by `remote` if it is `Some`.
+    fn poll_recv_from_peer(
+        &self,
+        buf: &mut [u8],
+        remote: Option<IpEndpoint>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<usize, RecvError>> {
+        if self.stopped() {
+            return Poll::Ready(Err(RecvError::Stopped));
+        }
+
+        self.with_mut(|s, _| loop {
+            self.drop_filtered(s);
+            match s.recv() {
+                Ok((data, meta)) => {
+                    if remote.is_some_and(|r| r != meta.endpoint) {
+                        // Not from our peer, drop it.
+                        self.record_rx_discarded();
+                        continue;
+                    }
+                    if buf.len() < data.len() {
+                        self.record_rx_discarded();
+                        return Poll::Ready(Err(RecvError::Truncated));
+                    }
+                    buf[..data.len()].copy_from_slice(data);
+                    self.record_rx(data.len());
+                    return Poll::Ready(Ok(data.len()));
+                }
+                Err(udp::RecvError::Truncated) => unreachable!(),
+                // No data ready
+                Err(udp::RecvError::Exhausted) => {
+                    s.register_recv_waker(cx.waker());
+                    return Poll::Pending;
+                }
+            }
+        })
+    }
+
+    /// Peek at the next datagram without removing it from the receive buffer.
+    ///
+    /// This method will wait until a datagram is received.
+    ///
+    /// Returns the number of bytes copied and the remote endpoint. A subsequent call to
+    /// [`recv_from`](Self::recv_from) returns the same datagram.
+    pub fn peek_from<'s>(
+        &'s self,
+        buf: &'s mut [u8],
+    ) -> impl Future<Output = Result<(usize, UdpMetadata), RecvError>> + 's {
+        poll_fn(|cx| self.poll_peek_from(buf, cx))
+    }
+
+    /// Peek at the next datagram without removing it from the receive buffer.
+    ///
+    /// When no datagram is available, this method will return `Poll::Pending` and
+    /// register the current task to be notified when a datagram is received.
+    ///
+    /// When a datagram is available, this method will return `Poll::Ready` with the
+    /// number of bytes copied and the remote endpoint. If `buf` is too small, no data
+    /// is copied and `Err(RecvError::Truncated)` is returned, leaving the datagram queued.
+    pub fn poll_peek_from(
+        &self,
+        buf: &mut [u8],
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(usize, UdpMetadata), RecvError>> {
+        if self.stopped() {
+            return Poll::Ready(Err(RecvError::Stopped));
+        }
+
+        self.with_mut(|s, _| {
+            self.drop_filtered(s);
+            match s.peek_slice(buf) {
+                Ok((n, meta)) => Poll::Ready(Ok((n, *meta))),
+                Err(udp::RecvError::Truncated) => Poll::Ready(Err(RecvError::Truncated)),
+                // No data ready
+                Err(udp::RecvError::Exhausted) => {
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
+    /// Receive a datagram, giving up if none arrives within `timeout`.
+    ///
+    /// Returns the number of bytes received and the remote endpoint, or
+    /// `Err(RecvTimeoutError::Timeout)` if the timeout expired first.
+    ///
+    /// Like [`recv_from`](Self::recv_from), this is cancel-safe: no datagram is
+    /// consumed unless it is returned.
+    pub async fn recv_from_with_timeout(
+        &self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<(usize, UdpMetadata), RecvTimeoutError> {
+        match self.recv_from(buf).with_timeout(timeout).await {
+            Ok(res) => Ok(res?),
+            Err(_) => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// Return a [`Stream`](futures_core::Stream) of received datagrams.
+    ///
+    /// Each item is a datagram copied into a `heapless::Vec` of capacity `N`, along with
+    /// the remote endpoint. Datagrams larger than `N` are discarded and yield
+    /// `Err(RecvError::Truncated)`. The stream never ends.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use core::future::poll_fn;
+    /// use core::pin::pin;
+    ///
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, RecvError, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use futures_core::Stream;
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 256];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 256];
+    /// let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// a.bind(1234).unwrap();
+    ///
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 256];
+    /// let mut b = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// b.bind(5678).unwrap();
+    ///
+    /// let test = async {
+    ///     let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    ///     for payload in [&b"one"[..], b"two", b"too long for 8", b"three"] {
+    ///         a.send_to(payload, remote).await.unwrap();
+    ///     }
+    ///
+    ///     let mut stream = pin!(b.recv_stream::<8>());
+    ///     let from = (Ipv4Address::new(10, 0, 0, 1), 1234).into();
+    ///     let datagram = |payload: &[u8]| Ok((heapless::Vec::from_slice(payload).unwrap(), from));
+    ///     let expected = [datagram(b"one"), datagram(b"two"), Err(RecvError::Truncated), datagram(b"three")];
+    ///     for item in expected {
+    ///         assert_eq!(poll_fn(|cx| stream.as_mut().poll_next(cx)).await, Some(item));
+    ///     }
+    /// };
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub fn recv_stream<const N: usize>(&self) -> RecvStream<'_, N> {
+        RecvStream { socket: self }
+    }
+
+    /// Send a request to `remote` and wait for its reply.
+    ///
+    /// The request is sent, then a reply from `remote` is awaited for up to `timeout`. If none
+    /// arrives in time, the request is retransmitted, up to `retries` times, before giving up
+    /// with `Err(RequestError::Timeout)`. Datagrams from other endpoints are dropped while waiting.
+    ///
+    /// On success, returns the size of the reply, written to `reply_buf`, and the endpoint it
+    /// came from. This is the usual exchange of protocols like SNTP, DNS or confirmable CoAP.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::join::join;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use embassy_time::{Duration, Timer};
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 256];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 256];
+    /// let mut client = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// client.bind(1234).unwrap();
+    ///
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 256];
+    /// let mut server = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// server.bind(5678).unwrap();
+    ///
+    /// // Lose the first attempt, then let the retransmission through.
+    /// state.set_drop_every(1);
+    /// let request = async {
+    ///     let mut reply = [0; 16];
+    ///     let remote = (Ipv4Address::new(10, 0, 0, 2), 5678).into();
+    ///     let (n, from) = client
+    ///         .request(b"ping", remote, &mut reply, 2, Duration::from_millis(200))
+    ///         .await
+    ///         .unwrap();
+    ///     assert_eq!(&reply[..n], b"pong");
+    ///     assert_eq!(from, remote);
+    /// };
+    /// let serve = async {
+    ///     Timer::after_millis(100).await;
+    ///     state.set_drop_every(0);
+    ///     let mut buf = [0; 16];
+    ///     let (n, meta) = server.recv_from(&mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..n], b"ping");
+    ///     server.send_to(b"pong", meta.endpoint).await.unwrap();
+    /// };
+    ///
+    /// match block_on(select3(join(request, serve), runner_a.run(), runner_b.run())) {
+    ///     Either3::First(_) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// assert_eq!(stack_a.device_stats().tx_dropped, 1);
+    /// # }
+    /// ```
+    pub async fn request(
+        &self,
+        payload: &[u8],
+        remote: IpEndpoint,
+        reply_buf: &mut [u8],
+        retries: u8,
+        timeout: Duration,
+    ) -> Result<(usize, IpEndpoint), RequestError> {
+        for _ in 0..=retries {
+            self.send_to(payload, remote).await.map_err(RequestError::from_send)?;
+            match poll_fn(|cx| self.poll_recv_from_peer(reply_buf, Some(remote), cx))
+                .with_timeout(timeout)
+                .await
+            {
+                Ok(res) => return Ok((res?, remote)),
+                Err(_) => continue,
+            }
+        }
+        Err(RequestError::Timeout)
+    }
+
+    /// Receive a datagram with a zero-copy function.
+    ///
+    /// When no datagram is available, this method will return `Poll::Pending` and
+    /// register the current task to be notified when a datagram is received.
+    ///
+    /// When a datagram is received, this method will call the provided function
+    /// with a reference to the received bytes and the remote endpoint and return
+    /// `Poll::Ready` with the function's returned value.
+    ///
+    /// This can't report [`RecvError::Stopped`], so it keeps waiting while the stack is stopped.
+    /// Use [`try_recv_from_with`](Self::try_recv_from_with) to stop waiting instead.
+    pub async fn recv_from_with<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&[u8], UdpMetadata) -> R,
+    {
+        let mut f = Some(f);
+        poll_fn(move |cx| self.poll_recv_with(&mut f, cx)).await
+    }
+
+    /// Receive a datagram with a zero-copy function, failing if the stack was stopped.
+    ///
+    /// Like [`recv_from_with`](Self::recv_from_with), but returns `Err(RecvError::Stopped)`
+    /// instead of waiting while the stack is stopped.
+    pub async fn try_recv_from_with<F, R>(&self, f: F) -> Result<R, RecvError>
+    where
+        F: FnOnce(&[u8], UdpMetadata) -> R,
+    {
+        let mut f = Some(f);
+        poll_fn(move |cx| {
+            if self.stopped() {
+                return Poll::Ready(Err(RecvError::Stopped));
+            }
+            self.poll_recv_with(&mut f, cx).map(Ok)
+        })
+        .await
+    }
+
+    fn poll_recv_with<F, R>(&self, f: &mut Option<F>, cx: &mut Context<'_>) -> Poll<R>
+    where
+        F: FnOnce(&[u8], UdpMetadata) -> R,
+    {
+        self.with_mut(|s, _| {
+            self.drop_filtered(s);
+            match s.recv() {
+                Ok((buffer, endpoint)) => {
+                    self.record_rx(buffer.len());
+                    Poll::Ready(unwrap!(f.take())(buffer, endpoint))
+                }
+                Err(udp::RecvError::Truncated) => unreachable!(),
+                Err(udp::RecvError::Exhausted) => {
+                    // socket buffer is empty wait until at least one byte has arrived
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
+    /// Wait until the socket becomes writable.
+    ///
+    /// A socket becomes writable when there is space in the buffer, from initial memory or after
+    /// dispatching datagrams on a full buffer.
+    pub fn wait_send_ready(&self) -> impl Future<Output = ()> + '_ {
+        self.send_waiting(|cx| self.poll_send_ready(cx))
+    }
+
+    /// Wait until a datagram can be sent.
+    ///
+    /// When no datagram can be sent (i.e. the buffer is full), this method will return
+    /// `Poll::Pending` and register the current task to be notified when
+    /// space is freed in the buffer after a datagram has been dispatched.
+    ///
+    /// When a datagram can be sent, this method will return `Poll::Ready`.
+    pub fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.with_mut(|s, _| {
+            if s.can_send() {
+                Poll::Ready(())
+            } else {
+                // socket buffer is full wait until a datagram has been dispatched
+                self.wait_send(s, cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Wait until the transmit buffer has room for a datagram of `bytes` bytes.
+    ///
+    /// If the socket's send buffer is too small to ever fit `bytes`, this method will return
+    /// `Err(SendError::PacketTooLarge)` immediately.
+    ///
+    /// smoltcp does not expose the contiguous free region of the buffer, so this waits for
+    /// `bytes` of total free space and a free metadata slot. If that space wraps around the end
+    /// of the buffer, a following send may still have to wait for more datagrams to be dispatched.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use core::future::Future;
+    /// use core::pin::pin;
+    /// use core::task::{Context, Poll, Waker};
+    ///
+    /// use embassy_futures::block_on;
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, SendError, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// let config = Config::ipv4_static(StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway: None,
+    ///     dns_servers: Default::default(),
+    /// });
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<2>::new();
+    /// let (stack, mut runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+    ///
+    /// let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    /// let mut rx = [0; 64];
+    /// let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    /// let mut tx = [0; 64];
+    /// let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+    /// socket.bind(1234).unwrap();
+    /// let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    /// let mut cx = Context::from_waker(Waker::noop());
+    ///
+    /// assert_eq!(block_on(socket.wait_send_capacity(100)), Err(SendError::PacketTooLarge));
+    ///
+    /// // 40 of the 64 bytes are queued, so 20 more fit but 40 don't.
+    /// block_on(socket.send_to(&[0; 40], remote)).unwrap();
+    /// assert_eq!(block_on(socket.wait_send_capacity(20)), Ok(()));
+    /// let mut wait = pin!(socket.wait_send_capacity(40));
+    /// assert!(wait.as_mut().poll(&mut cx).is_pending());
+    ///
+    /// // Dispatching the queued datagram frees the space.
+    /// let mut run = pin!(runner.run());
+    /// let _ = run.as_mut().poll(&mut cx);
+    /// assert_eq!(wait.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+    /// # }
+    /// ```
+    pub fn wait_send_capacity(&self, bytes: usize) -> impl Future<Output = Result<(), SendError>> + '_ {
+        self.send_waiting(move |cx| self.poll_send_capacity(bytes, cx))
+    }
+
+    /// Wait until the transmit buffer has room for a datagram of `bytes` bytes.
+    ///
+    /// When there is not enough free space, this method will return `Poll::Pending` and register
+    /// the current task to be notified when a datagram has been dispatched.
+    ///
+    /// See [`wait_send_capacity`](Self::wait_send_capacity) for details.
+    pub fn poll_send_capacity(&self, bytes: usize, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        let send_capacity_too_small = self.with(|s, _| s.payload_send_capacity() < bytes);
+        if send_capacity_too_small {
+            return Poll::Ready(Err(SendError::PacketTooLarge));
+        }
+
+        let stopped = self.stopped();
+        self.with_mut(|s, _| {
+            if stopped {
+                Poll::Ready(Err(SendError::Stopped))
+            } else if s.can_send() && s.payload_send_capacity() - s.send_queue() >= bytes {
+                Poll::Ready(Ok(()))
+            } else {
+                self.wait_send(s, cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Send a datagram to the specified remote endpoint.
+    ///
+    /// This method will wait until the datagram has been sent.
+    ///
+    /// An empty `buf` sends a datagram without payload, which the peer receives as a 0-length read.
+    ///
+    /// If the socket's send buffer is too small to fit `buf`, this method will return `Err(SendError::PacketTooLarge)`
+    ///
+    /// When the remote endpoint is not reachable, this method will return `Err(SendError::NoRoute)`
+    pub async fn send_to<T>(&self, buf: &[u8], remote_endpoint: T) -> Result<(), SendError>
+    where
+        T: Into<UdpMetadata>,
+    {
+        let remote_endpoint: UdpMetadata = remote_endpoint.into();
+        self.send_waiting(move |cx| self.poll_send_to(buf, remote_endpoint, cx))
+            .await
+    }
+
+    /// Send a datagram to the specified remote endpoint, giving up at `deadline`.
+    ///
+    /// Like [`send_to`](Self::send_to), but returns `Err(SendTimeoutError::Timeout)` if the
+    /// send buffer did not have room for the datagram before `deadline`, e.g. because the
+    /// driver stopped transmitting. The datagram is not queued in that case.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select, Either};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, SendTimeoutError, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use embassy_time::{Duration, Instant};
+    ///
+    /// let config = Config::ipv4_static(StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway: None,
+    ///     dns_servers: Default::default(),
+    /// });
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<2>::new();
+    /// let (stack, mut runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+    ///
+    /// // A single transmit slot, and no runner yet to dispatch it.
+    /// let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    /// let mut rx = [0; 64];
+    /// let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    /// let mut tx = [0; 64];
+    /// let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+    /// socket.bind(1234).unwrap();
+    /// let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    ///
+    /// let later = Instant::now() + Duration::from_secs(1);
+    /// assert_eq!(block_on(socket.send_to_before(b"first", remote, later)), Ok(()));
+    ///
+    /// let start = Instant::now();
+    /// let deadline = start + Duration::from_millis(50);
+    /// let res = block_on(socket.send_to_before(b"second", remote, deadline));
+    /// assert_eq!(res, Err(SendTimeoutError::Timeout));
+    /// assert!(start.elapsed() >= Duration::from_millis(50));
+    ///
+    /// // Once the runner dispatches the queued datagram, sending succeeds again.
+    /// let later = Instant::now() + Duration::from_secs(1);
+    /// match block_on(select(socket.send_to_before(b"third", remote, later), runner.run())) {
+    ///     Either::First(res) => assert_eq!(res, Ok(())),
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub async fn send_to_before<T>(
+        &self,
+        buf: &[u8],
+        remote_endpoint: T,
+        deadline: Instant,
+    ) -> Result<(), SendTimeoutError>
+    where
+        T: Into<UdpMetadata>,
+    {
+        match self.send_to(buf, remote_endpoint).with_deadline(deadline).await {
+            Ok(res) => Ok(res?),
+            Err(_) => Err(SendTimeoutError::Timeout),
+        }
+    }
+
+    /// Send a datagram to the specified remote endpoint.
+    ///
+    /// When the datagram has been sent, this method will return `Poll::Ready(Ok())`.
+    ///
+    /// When the socket's send buffer is full, this method will return `Poll::Pending`
+    /// and register the current task to be notified when the buffer has space available.
+    ///
+    /// If the socket's send buffer is too small to fit `buf`, this method will return `Poll::Ready(Err(SendError::PacketTooLarge))`
+    ///
+    /// When the remote endpoint is not reachable, this method will return `Poll::Ready(Err(Error::NoRoute))`.
+    pub fn poll_send_to<T>(&self, buf: &[u8], remote_endpoint: T, cx: &mut Context<'_>) -> Poll<Result<(), SendError>>
+    where
+        T: Into<UdpMetadata>,
+    {
+        // Don't need to wake waker in `with_mut` if the buffer will never fit the udp tx_buffer.
+        let send_capacity_too_small = self.with(|s, _| s.payload_send_capacity() < buf.len());
+        if send_capacity_too_small {
+            return Poll::Ready(Err(SendError::PacketTooLarge));
+        }
+
+        if self.stopped() {
+            return Poll::Ready(Err(SendError::Stopped));
+        }
+
+        self.with_mut(|s, _| match s.send_slice(buf, remote_endpoint) {
+            // Entire datagram has been sent
+            Ok(()) => {
+                self.record_tx(buf.len());
+                Poll::Ready(Ok(()))
+            }
+            Err(udp::SendError::BufferFull) => {
+                self.record_tx_blocked();
+                self.wait_send(s, cx.waker());
+                Poll::Pending
+            }
+            Err(udp::SendError::Unaddressable) => {
+                // If no sender/outgoing port is specified, there is not really "no route"
+                if s.endpoint().port == 0 {
+                    Poll::Ready(Err(SendError::SocketNotBound))
+                } else {
+                    Poll::Ready(Err(SendError::NoRoute))
+                }
+            }
+        })
+    }
+
+    /// Send the same datagram to each of `endpoints`, in order.
+    ///
+    /// This waits for transmit buffer space before each send, like [`send_to`](Self::send_to).
+    /// It stops at the first error, which is returned along with the number of endpoints the
+    /// datagram was already queued for.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, RecvTimeoutError, SendAllError, SendError, UdpSocket};
+    /// use embassy_net::{Config, IpEndpoint, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use embassy_time::Duration;
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<4>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 256];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 256];
+    /// let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// a.bind(1234).unwrap();
+    ///
+    /// let mut bufs = [(); 3].map(|_| ([PacketMetadata::EMPTY; 4], [0; 256], [PacketMetadata::EMPTY; 4], [0; 256]));
+    /// let mut receivers = Vec::new();
+    /// for (port, (rx_meta, rx, tx_meta, tx)) in (5001..).zip(&mut bufs) {
+    ///     let mut socket = UdpSocket::new(stack_b, rx_meta, rx, tx_meta, tx);
+    ///     socket.bind(port).unwrap();
+    ///     receivers.push(socket);
+    /// }
+    ///
+    /// let endpoint = |port| IpEndpoint::new(Ipv4Address::new(10, 0, 0, 2).into(), port);
+    /// let test = async {
+    ///     a.send_to_all(b"hello", &[endpoint(5001), endpoint(5002), endpoint(5003)])
+    ///         .await
+    ///         .unwrap();
+    ///     let mut buf = [0; 16];
+    ///     for socket in &receivers {
+    ///         let (n, _) = socket.recv_from(&mut buf).await.unwrap();
+    ///         assert_eq!(&buf[..n], b"hello");
+    ///     }
+    ///
+    ///     // Port 0 can't be sent to, so the last endpoint is never reached.
+    ///     let res = a.send_to_all(b"again", &[endpoint(5001), endpoint(0), endpoint(5003)]).await;
+    ///     assert_eq!(res, Err(SendAllError { sent: 1, error: SendError::NoRoute }));
+    ///     let (n, _) = receivers[0].recv_from(&mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..n], b"again");
+    ///     let res = receivers[2].recv_from_with_timeout(&mut buf, Duration::from_millis(50)).await;
+    ///     assert_eq!(res, Err(RecvTimeoutError::Timeout));
+    /// };
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub async fn send_to_all(&self, buf: &[u8], endpoints: &[IpEndpoint]) -> Result<(), SendAllError> {
+        for (sent, endpoint) in endpoints.iter().enumerate() {
+            self.send_to(buf, *endpoint)
+                .await
+                .map_err(|error| SendAllError { sent, error })?;
+        }
+        Ok(())
+    }
+
+    /// Send a datagram to `port` on the host named `host`, resolving it with `resolver`.
+    ///
+    /// Any [`embedded_nal_async::Dns`] implementation can be used as the resolver, such as
+    /// [`DnsSocket`](crate::dns::DnsSocket). The resolved address is stored in `cache`, so later
+    /// sends to the same host don't query the resolver again until the stack's configuration
+    /// changes. A cache can be shared by several sockets of the same stack.
+    ///
+    /// If resolving fails, this returns `Err(SendToHostError::Resolve)` with the resolver's
+    /// error. If the host resolves to an address family the stack doesn't support, this
+    /// returns `Err(SendToHostError::Send(SendError::NoRoute))`. Otherwise it behaves like
+    /// [`send_to`](Self::send_to).
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use core::cell::Cell;
+    /// use core::net::{IpAddr, Ipv4Addr};
+    ///
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{HostCache, PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use embedded_nal_async::{AddrType, Dns};
+    ///
+    /// struct FixedResolver(Cell<u32>);
+    ///
+    /// impl Dns for FixedResolver {
+    ///     type Error = core::convert::Infallible;
+    ///
+    ///     async fn get_host_by_name(&self, _host: &str, _addr_type: AddrType) -> Result<IpAddr, Self::Error> {
+    ///         self.0.set(self.0.get() + 1);
+    ///         Ok(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+    ///     }
+    ///
+    ///     async fn get_host_by_address(&self, _addr: IpAddr, _result: &mut [u8]) -> Result<usize, Self::Error> {
+    ///         unimplemented!()
+    ///     }
+    /// }
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 256];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 256];
+    /// let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// a.bind(1234).unwrap();
+    ///
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 256];
+    /// let mut b = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// b.bind(5678).unwrap();
+    ///
+    /// let resolver = FixedResolver(Cell::new(0));
+    /// let mut cache = HostCache::new();
+    /// let test = async {
+    ///     let mut buf = [0; 16];
+    ///     for expected_lookups in [1, 1, 2] {
+    ///         if expected_lookups == 2 {
+    ///             // Applying a configuration invalidates the cache.
+    ///             stack_a.set_config_v4(embassy_net::ConfigV4::Static(stack_a.config_v4().unwrap()));
+    ///         }
+    ///         a.send_to_host(b"hello", "peer.example", 5678, &resolver, &mut cache)
+    ///             .await
+    ///             .unwrap();
+    ///         assert_eq!(resolver.0.get(), expected_lookups);
+    ///         let (n, meta) = b.recv_from(&mut buf).await.unwrap();
+    ///         assert_eq!(&buf[..n], b"hello");
+    ///         assert_eq!(meta.endpoint.port, 1234);
+    ///     }
+    /// };
+    ///
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "dns")]
+    pub async fn send_to_host<R>(
+        &self,
+        buf: &[u8],
+        host: &str,
+        port: u16,
+        resolver: &R,
+        cache: &mut HostCache,
+    ) -> Result<(), SendToHostError<R::Error>>
+    where
+        R: embedded_nal_async::Dns,
+    {
+        let generation = self.stack.config_generation();
+        let addr = match cache.get(host, generation) {
+            Some(addr) => addr,
+            None => {
+                #[cfg(all(feature = "proto-ipv4", feature = "proto-ipv6"))]
+                let addr_type = embedded_nal_async::AddrType::Either;
+                #[cfg(not(feature = "proto-ipv6"))]
+                let addr_type = embedded_nal_async::AddrType::IPv4;
+                #[cfg(not(feature = "proto-ipv4"))]
+                let addr_type = embedded_nal_async::AddrType::IPv6;
+
+                let addr = resolver
+                    .get_host_by_name(host, addr_type)
+                    .await
+                    .map_err(SendToHostError::Resolve)?;
+                let addr = resolved_address(addr).ok_or(SendToHostError::Send(SendError::NoRoute))?;
+                cache.insert(host, addr, generation);
+                addr
+            }
+        };
+        self.send_to(buf, IpEndpoint::new(addr, port))
+            .await
+            .map_err(SendToHostError::Send)
+    }
+
+    /// Send a datagram to the IPv4 limited broadcast address (`255.255.255.255`) on `port`.
+    ///
+    /// This is a shorthand for [`send_to`](Self::send_to). Broadcast endpoints, including
+    /// subnet-directed ones, can also be passed to `send_to` directly.
+    #[cfg(feature = "proto-ipv4")]
+    pub async fn send_broadcast(&self, buf: &[u8], port: u16) -> Result<(), SendError> {
+        self.send_to(buf, IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), port))
+            .await
+    }
+
+    /// Send a datagram to the connected remote endpoint.
+    ///
+    /// This method will wait until the datagram has been sent.
+    ///
+    /// If the socket is not connected, this method will return `Err(SendError::NotConnected)`.
+    /// Otherwise it behaves like [`send_to`](Self::send_to).
+    pub async fn send(&self, buf: &[u8]) -> Result<(), SendError> {
+        self.send_waiting(|cx| self.poll_send(buf, cx)).await
+    }
+
+    /// Send a datagram to the connected remote endpoint.
+    ///
+    /// If the socket is not connected, this method will return `Poll::Ready(Err(SendError::NotConnected))`.
+    /// Otherwise it behaves like [`poll_send_to`](Self::poll_send_to).
+    pub fn poll_send(&self, buf: &[u8], cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        match self.remote {
+            Some(remote) => self.poll_send_to(buf, remote, cx),
+            None => Poll::Ready(Err(SendError::NotConnected)),
+        }
+    }
+
+    /// Send a datagram to the specified remote endpoint with a zero-copy function.
+    ///
+    /// This method will wait until the buffer can fit the requested size before
+    /// calling the function to fill its contents.
+    ///
+    /// If the socket's send buffer is too small to fit `size`, this method will return `Err(SendError::PacketTooLarge)`
+    ///
+    /// When the remote endpoint is not reachable, this method will return `Err(SendError::NoRoute)`
+    pub async fn send_to_with<T, F, R>(&mut self, size: usize, remote_endpoint: T, f: F) -> Result<R, SendError>
+    where
+        T: Into<UdpMetadata> + Copy,
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        // Don't need to wake waker in `with_mut` if the buffer will never fit the udp tx_buffer.
+        let send_capacity_too_small = self.with(|s, _| s.payload_send_capacity() < size);
+        if send_capacity_too_small {
+            return Err(SendError::PacketTooLarge);
+        }
+
+        let mut f = Some(f);
+        let this = &*self;
+        this.send_waiting(move |cx| {
+            if this.stopped() {
+                return Poll::Ready(Err(SendError::Stopped));
+            }
+
+            this.with_mut(|s, _| {
+                match s.send(size, remote_endpoint) {
+                    Ok(buffer) => {
+                        this.record_tx(size);
+                        Poll::Ready(Ok(unwrap!(f.take())(buffer)))
+                    }
+                    Err(udp::SendError::BufferFull) => {
+                        this.record_tx_blocked();
+                        this.wait_send(s, cx.waker());
+                        Poll::Pending
+                    }
+                    Err(udp::SendError::Unaddressable) => {
+                        // If no sender/outgoing port is specified, there is not really "no route"
+                        if s.endpoint().port == 0 {
+                            Poll::Ready(Err(SendError::SocketNotBound))
+                        } else {
+                            Poll::Ready(Err(SendError::NoRoute))
+                        }
+                    }
+                }
+            })
+        })
+        .await
+    }
+
+    /// Flush the socket.
+    ///
+    /// This method will wait until the socket is flushed.
+    pub fn flush(&mut self) -> impl Future<Output = ()> + '_ {
+        let this = &*self;
+        this.send_waiting(move |cx| {
+            this.with_mut(|s, _| {
+                if s.send_queue() == 0 {
+                    Poll::Ready(())
+                } else {
+                    this.wait_send(s, cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+    }
+
+    /// Returns the local endpoint of the socket.
+    pub fn endpoint(&self) -> IpListenEndpoint {
+        self.with(|s, _| s.endpoint())
+    }
+
+    /// Returns the local endpoint of the socket, with the address resolved.
+    ///
+    /// If the socket is bound to a specific address, that address is returned. If it is bound
+    /// to all addresses, the first address configured on the interface is returned instead.
+    ///
+    /// Returns `None` if the socket is not bound, or if it is bound to all addresses and the
+    /// stack has no IP configuration yet.
+    pub fn local_endpoint(&self) -> Option<IpEndpoint> {
+        self.with(|s, i| {
+            let endpoint = s.endpoint();
+            if endpoint.port == 0 {
+                return None;
+            }
+            let addr = match endpoint.addr {
+                Some(addr) => addr,
+                None => i.ip_addrs().first()?.address(),
+            };
+            Some(IpEndpoint::new(addr, endpoint.port))
+        })
+    }
+
+    /// Wait until the IP addresses of the interface change.
+    ///
+    /// This resolves on the first address change after the call, e.g. when DHCP obtains a
+    /// different lease or loses its lease, or a new static configuration is set. A socket
+    /// bound to all addresses keeps running across such a change, so this lets it find out
+    /// that its [`local_endpoint`](Self::local_endpoint) is now different.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use core::future::Future;
+    /// use core::pin::pin;
+    /// use core::sync::atomic::{AtomicUsize, Ordering};
+    /// use core::task::{Context, Waker};
+    /// use std::sync::Arc;
+    /// use std::task::Wake;
+    ///
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, ConfigV4, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// struct Counter(AtomicUsize);
+    ///
+    /// impl Wake for Counter {
+    ///     fn wake(self: Arc<Self>) {
+    ///         self.0.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// }
+    ///
+    /// fn config(addr: Ipv4Address) -> StaticConfigV4 {
+    ///     StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     }
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<2>::new();
+    /// let initial = Config::ipv4_static(config(Ipv4Address::new(10, 0, 0, 1)));
+    /// let (stack, _runner) = embassy_net::new(state.device(), initial, &mut resources, 1);
+    ///
+    /// let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    /// let mut rx = [0; 64];
+    /// let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    /// let mut tx = [0; 64];
+    /// let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+    /// socket.bind(1234).unwrap();
+    ///
+    /// let counter = Arc::new(Counter(AtomicUsize::new(0)));
+    /// let waker = Waker::from(counter.clone());
+    /// let mut cx = Context::from_waker(&waker);
+    /// let mut changed = pin!(socket.wait_address_changed());
+    /// assert!(changed.as_mut().poll(&mut cx).is_pending());
+    ///
+    /// // Applying the same configuration again doesn't change the address.
+    /// stack.set_config_v4(ConfigV4::Static(config(Ipv4Address::new(10, 0, 0, 1))));
+    /// assert_eq!(counter.0.load(Ordering::Relaxed), 0);
+    ///
+    /// stack.set_config_v4(ConfigV4::Static(config(Ipv4Address::new(10, 0, 0, 5))));
+    /// assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+    /// assert!(changed.as_mut().poll(&mut cx).is_ready());
+    /// assert_eq!(socket.local_endpoint(), Some((Ipv4Address::new(10, 0, 0, 5), 1234).into()));
+    /// # }
+    /// ```
+    pub async fn wait_address_changed(&self) {
+        let generation = self.stack.with(|i| i.addr_generation);
+        poll_fn(|cx| {
+            self.stack.with_mut(|i| {
+                if i.addr_generation != generation {
+                    Poll::Ready(())
+                } else {
+                    i.addr_waker.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Returns whether the socket is open.
+    ///
+    /// A UDP socket is open exactly when it has been bound to a local port with
+    /// [`bind`](Self::bind), and is no longer open after [`close`](Self::close).
+    pub fn is_open(&self) -> bool {
+        self.with(|s, _| s.is_open())
+    }
+
+    /// Close the socket.
+    pub fn close(&mut self) {
+        self.with_mut(|s, _| s.close())
+    }
+
+    /// Close the socket once all queued datagrams have been sent.
+    ///
+    /// This waits until the transmit buffer has been [flushed](Self::flush), then closes the
+    /// socket. Unlike [`close`](Self::close), which discards datagrams still in the transmit
+    /// buffer, this ensures they are handed to the driver first.
+    pub async fn close_graceful(&mut self) {
+        self.flush().await;
+        self.close();
+    }
+
+    /// Returns whether the socket is ready to send data, i.e. it has enough buffer space to hold a packet.
+    pub fn may_send(&self) -> bool {
+        self.with(|s, _| s.can_send())
+    }
+
+    /// Returns whether the socket is ready to receive data, i.e. it has received a packet that's now in the buffer.
+    pub fn may_recv(&self) -> bool {
+        self.with(|s, _| s.can_recv())
+    }
+
+    /// Return the maximum number packets the socket can receive.
+    pub fn packet_recv_capacity(&self) -> usize {
+        self.with(|s, _| s.packet_recv_capacity())
+    }
+
+    /// Return the maximum number packets the socket can receive.
+    pub fn packet_send_capacity(&self) -> usize {
+        self.with(|s, _| s.packet_send_capacity())
+    }
+
+    /// Return the maximum number of bytes inside the recv buffer.
+    pub fn payload_recv_capacity(&self) -> usize {
+        self.with(|s, _| s.payload_recv_capacity())
+    }
+
+    /// Return the maximum number of bytes inside the transmit buffer.
+    pub fn payload_send_capacity(&self) -> usize {
+        self.with(|s, _| s.payload_send_capacity())
+    }
+
+    /// Return the amount of octets queued in the transmit buffer.
+    ///
+    /// The free space is at most [`payload_send_capacity()`](Self::payload_send_capacity) minus this value,
+    /// as a datagram must also fit in a contiguous region of the buffer.
+    pub fn send_queue(&self) -> usize {
+        self.with(|s, _| s.send_queue())
+    }
+
+    /// Return the amount of octets queued in the receive buffer.
+    ///
+    /// The free space is at most [`payload_recv_capacity()`](Self::payload_recv_capacity) minus this value,
+    /// as a datagram must also fit in a contiguous region of the buffer.
+    pub fn recv_queue(&self) -> usize {
+        self.with(|s, _| s.recv_queue())
+    }
+
+    /// Return the hop limit field used in the IP header of sent packets.
+    ///
+    /// `None` means the interface default is used.
+    pub fn hop_limit(&self) -> Option<u8> {
+        self.with(|s, _| s.hop_limit())
+    }
+
+    /// Set the hop limit field in the IP header of sent packets.
+    pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
+        self.with_mut(|s, _| s.set_hop_limit(hop_limit))
+    }
+
+    /// Join a multicast group.
+    ///
+    /// Group membership belongs to the interface, so it is shared with every other socket
+    /// on the stack. Each socket holds one reference to the groups it joined, counted like
+    /// [`Stack::join_multicast_group`]: the interface stays in a group until every socket
+    /// and every stack-level join has left it. A socket leaves its groups when dropped.
+    ///
+    /// Joining a group this socket already joined does nothing. Returns whether the
+    /// interface membership changed, i.e. `false` if the group was already joined.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    ///
+    /// let config = Config::ipv4_static(StaticConfigV4 {
+    ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+    ///     gateway: None,
+    ///     dns_servers: Default::default(),
+    /// });
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let mut resources = StackResources::<3>::new();
+    /// let (stack, _runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+    /// let group = Ipv4Address::new(224, 0, 0, 251);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 1];
+    /// let mut rx_a = [0; 64];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 1];
+    /// let mut tx_a = [0; 64];
+    /// let mut a = UdpSocket::new(stack, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 1];
+    /// let mut rx_b = [0; 64];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 1];
+    /// let mut tx_b = [0; 64];
+    /// let mut b = UdpSocket::new(stack, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    ///
+    /// assert_eq!(a.join_multicast_group(group), Ok(true));
+    /// assert_eq!(b.join_multicast_group(group), Ok(false));
+    /// stack.join_multicast_group(group).unwrap();
+    ///
+    /// // Dropping a socket releases its membership, but the others still hold the group.
+    /// drop(a);
+    /// assert!(stack.has_multicast_group(group));
+    /// assert_eq!(b.leave_multicast_group(group), Ok(false));
+    /// // Leaving again from the same socket doesn't release the stack's reference.
+    /// assert_eq!(b.leave_multicast_group(group), Ok(false));
+    /// assert!(stack.has_multicast_group(group));
+    ///
+    /// stack.leave_multicast_group(group).unwrap();
+    /// assert!(!stack.has_multicast_group(group));
+    /// # }
+    /// ```
+    #[cfg(feature = "multicast")]
+    pub fn join_multicast_group(&mut self, addr: impl Into<IpAddress>) -> Result<bool, MulticastError> {
+        let addr = addr.into();
+        if self.multicast_groups.contains(&addr) {
+            return Ok(false);
+        }
+        if self.multicast_groups.is_full() {
+            return Err(MulticastError::GroupTableFull);
+        }
+        let joined = self.stack.with_mut(|i| i.join_multicast_group(addr))?;
+        // NOTE(unwrap): we checked there is room above.
+        unwrap!(self.multicast_groups.push(addr).ok());
+        Ok(joined)
+    }
+
+    /// Leave a multicast group.
+    ///
+    /// Leaving a group this socket didn't join does nothing. Returns whether the interface
+    /// membership changed, i.e. `false` if the group is still joined by another socket or
+    /// the stack.
+    #[cfg(feature = "multicast")]
+    pub fn leave_multicast_group(&mut self, addr: impl Into<IpAddress>) -> Result<bool, MulticastError> {
+        let addr = addr.into();
+        let Some(pos) = self.multicast_groups.iter().position(|a| *a == addr) else {
+            return Ok(false);
+        };
+        let left = self.stack.with_mut(|i| i.leave_multicast_group(addr))?;
+        self.multicast_groups.swap_remove(pos);
+        Ok(left)
+    }
+
+    /// Split the socket into receive and transmit halves.
+    ///
+    /// The halves can be used concurrently, e.g. from two futures joined together.
+    pub fn split(&mut self) -> (UdpRx<'_>, UdpTx<'_>) {
+        (UdpRx { socket: self }, UdpTx { socket: self })
+    }
+}
+
+/// The receive half of a UDP socket.
+pub struct UdpRx<'a> {
+    socket: &'a UdpSocket<'a>,
+}
+
+/// The transmit half of a UDP socket.
+pub struct UdpTx<'a> {
+    socket: &'a UdpSocket<'a>,
+}
+
+impl<'a> UdpRx<'a> {
+    /// Wait until the socket becomes readable.
+    ///
+    /// See [`UdpSocket::wait_recv_ready`].
+    pub fn wait_recv_ready(&self) -> impl Future<Output = ()> + '_ {
+        self.socket.wait_recv_ready()
+    }
+
+    /// Receive a datagram.
+    ///
+    /// See [`UdpSocket::recv_from`].
+    pub fn recv_from<'s>(
+        &'s self,
+        buf: &'s mut [u8],
+    ) -> impl Future<Output = Result<(usize, UdpMetadata), RecvError>> + 's {
+        self.socket.recv_from(buf)
+    }
+
+    /// Receive a datagram.
+    ///
+    /// See [`UdpSocket::poll_recv_from`].
+    pub fn poll_recv_from(
+        &self,
+        buf: &mut [u8],
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(usize, UdpMetadata), RecvError>> {
+        self.socket.poll_recv_from(buf, cx)
+    }
+
+    /// Receive a datagram from the connected remote endpoint.
+    ///
+    /// See [`UdpSocket::recv`].
+    pub fn recv<'s>(&'s self, buf: &'s mut [u8]) -> impl Future<Output = Result<usize, RecvError>> + 's {
+        self.socket.recv(buf)
+    }
+
+    /// Peek at the next datagram without removing it from the receive buffer.
+    ///
+    /// See [`UdpSocket::peek_from`].
+    pub fn peek_from<'s>(
+        &'s self,
+        buf: &'s mut [u8],
+    ) -> impl Future<Output = Result<(usize, UdpMetadata), RecvError>> + 's {
+        self.socket.peek_from(buf)
+    }
+}
+
+impl<'a> UdpTx<'a> {
+    /// Wait until the socket becomes writable.
+    ///
+    /// See [`UdpSocket::wait_send_ready`].
+    pub fn wait_send_ready(&self) -> impl Future<Output = ()> + '_ {
+        self.socket.wait_send_ready()
+    }
+
+    /// Send a datagram to the specified remote endpoint.
+    ///
+    /// See [`UdpSocket::send_to`].
+    pub async fn send_to<T>(&self, buf: &[u8], remote_endpoint: T) -> Result<(), SendError>
+    where
+        T: Into<UdpMetadata>,
+    {
+        self.socket.send_to(buf, remote_endpoint).await
+    }
+
+    /// Send a datagram to the specified remote endpoint.
+    ///
+    /// See [`UdpSocket::poll_send_to`].
+    pub fn poll_send_to<T>(&self, buf: &[u8], remote_endpoint: T, cx: &mut Context<'_>) -> Poll<Result<(), SendError>>
+    where
+        T: Into<UdpMetadata>,
+    {
+        self.socket.poll_send_to(buf, remote_endpoint, cx)
+    }
+
+    /// Send a datagram to the connected remote endpoint.
+    ///
+    /// See [`UdpSocket::send`].
+    pub async fn send(&self, buf: &[u8]) -> Result<(), SendError> {
+        self.socket.send(buf).await
+    }
+}
+
+/// Stream of datagrams received by a UDP socket.
+///
+/// Created by [`UdpSocket::recv_stream`].
+pub struct RecvStream<'a, const N: usize> {
+    socket: &'a UdpSocket<'a>,
+}
+
+impl<'a, const N: usize> futures_core::Stream for RecvStream<'a, N> {
+    type Item = Result<(heapless::Vec<u8, N>, IpEndpoint), RecvError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut buf = heapless::Vec::new();
+        // NOTE(unwrap): the new length is the vec's capacity.
+        unwrap!(buf.resize_default(N).ok());
+        self.socket.poll_recv_from(&mut buf, cx).map(|res| {
+            Some(res.map(|(n, meta)| {
+                buf.truncate(n);
+                (buf, meta.endpoint)
+            }))
+        })
+    }
+}
+
+impl Drop for UdpSocket<'_> {
+    fn drop(&mut self) {
+        self.stack.with_mut(|i| {
+            #[cfg(feature = "multicast")]
+            for addr in &self.multicast_groups {
+                let _ = i.leave_multicast_group(*addr);
+            }
+            i.sockets.remove(self.handle)
+        });
+    }
+}
+
+/// Tasks waiting for transmit buffer space, see [`UdpSocket`].
+struct SendQueue {
+    /// Async send operations, in the order they started waiting.
+    waiters: Vec<(u32, Waker), SEND_WAITERS>,
+    /// The last caller of a `poll_*` method that had to wait.
+    poller: Option<Waker>,
+    /// The async send operation being polled, if any.
+    current: Option<u32>,
+    ids: u32,
+}
+
+impl SendQueue {
+    const fn new() -> Self {
+        Self {
+            waiters: Vec::new(),
+            poller: None,
+            current: None,
+            ids: 0,
+        }
+    }
+
+    fn next_id(&mut self) -> u32 {
+        self.ids = self.ids.wrapping_add(1);
+        self.ids
+    }
+}
+
+/// Leaves the send queue of a socket when a waiting send future is dropped.
+struct SendWaiter<'s, 'a> {
+    socket: &'s UdpSocket<'a>,
+    id: u32,
+}
+
+impl Drop for SendWaiter<'_, '_> {
+    fn drop(&mut self) {
+        self.socket.stop_waiting_send(self.id);
+    }
+}
+
+fn _assert_covariant<'a, 'b: 'a>(x: UdpSocket<'b>) -> UdpSocket<'a> {
+    x
+}
+
+/// Statically allocated buffers for a single [`UdpSocket`].
+///
+/// Usually created through the [`udp_buffers!`](crate::udp_buffers) macro.
+pub struct UdpBuffers<const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize> {
+    taken: Mutex<CriticalSectionRawMutex, Cell<bool>>,
+    rx_meta: UnsafeCell<[PacketMetadata; RX_META]>,
+    rx: UnsafeCell<[u8; RX_SZ]>,
+    tx_meta: UnsafeCell<[PacketMetadata; TX_META]>,
+    tx: UnsafeCell<[u8; TX_SZ]>,
+}
+
+unsafe impl<const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize> Sync
+    for UdpBuffers<RX_META, RX_SZ, TX_META, TX_SZ>
+{
+}
+
+impl<const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize>
+    UdpBuffers<RX_META, RX_SZ, TX_META, TX_SZ>
+{
+    /// Create new buffers.
+    pub const fn new() -> Self {
+        Self {
+            taken: Mutex::new(Cell::new(false)),
+            rx_meta: UnsafeCell::new([PacketMetadata::EMPTY; RX_META]),
+            rx: UnsafeCell::new([0; RX_SZ]),
+            tx_meta: UnsafeCell::new([PacketMetadata::EMPTY; TX_META]),
+            tx: UnsafeCell::new([0; TX_SZ]),
+        }
+    }
+
+    /// Take the buffers, in the order expected by [`UdpSocket::new`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffers have already been taken.
+    #[allow(clippy::mut_from_ref)]
+    pub fn take(
+        &'static self,
+    ) -> (
+        &'static mut [PacketMetadata],
+        &'static mut [u8],
+        &'static mut [PacketMetadata],
+        &'static mut [u8],
+    ) {
+        let taken = self.taken.lock(|t| t.replace(true));
+        assert!(!taken, "UdpBuffers already taken");
+
+        // Safety: the flag above guarantees the buffers are only handed out once.
+        unsafe {
+            (
+                &mut *self.rx_meta.get(),
+                &mut *self.rx.get(),
+                &mut *self.tx_meta.get(),
+                &mut *self.tx.get(),
+            )
+        }
+    }
+}
+
+impl<const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize> Default
+    for UdpBuffers<RX_META, RX_SZ, TX_META, TX_SZ>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Allocate static buffers for a [`UdpSocket`].
+///
+/// Takes the number of receive packets, receive bytes, transmit packets and
+/// transmit bytes, and evaluates to a tuple of the four buffers ready to pass to
+/// [`UdpSocket::new`]. Each invocation allocates its own buffers, which can only
+/// be taken once: evaluating the same invocation again (e.g. in a loop) panics
+/// instead of handing out the same memory to two sockets.
+///
+/// ```no_run
+/// use embassy_net::udp::UdpSocket;
+/// use embassy_net::{udp_buffers, Stack};
+///
+/// fn sockets(stack: Stack<'static>) -> (UdpSocket<'static>, UdpSocket<'static>) {
+///     let (rx_meta, rx_buffer, tx_meta, tx_buffer) = udp_buffers!(4, 1024, 4, 1024);
+///     let control = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+///
+///     let (rx_meta, rx_buffer, tx_meta, tx_buffer) = udp_buffers!(16, 4096, 4, 512);
+///     let data = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+///
+///     (control, data)
+/// }
+/// ```
+#[macro_export]
+macro_rules! udp_buffers {
+    ($rx_packets:expr, $rx_bytes:expr, $tx_packets:expr, $tx_bytes:expr) => {{
+        static BUFFERS: $crate::udp::UdpBuffers<{ $rx_packets }, { $rx_bytes }, { $tx_packets }, { $tx_bytes }> =
+            $crate::udp::UdpBuffers::new();
+        BUFFERS.take()
+    }};
+}
+
+struct PoolBuffers<const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize> {
+    rx_meta: [PacketMetadata; RX_META],
+    rx: [u8; RX_SZ],
+    tx_meta: [PacketMetadata; TX_META],
+    tx: [u8; TX_SZ],
+}
+
+/// Buffers for up to `N` [`UdpSocket`]s alive at the same time.
+///
+/// Sockets created with [`UdpSocket::new_pooled`] borrow one set of buffers from the pool,
+/// and give it back when they are dropped. This bounds the buffer memory by the number of
+/// sockets in use at once rather than the number of sockets the firmware ever creates.
+///
+/// The pool is not `Sync`, so it can only be shared by sockets of the same executor.
+///
+/// ```
+/// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+/// # {
+/// use embassy_net::loopback::LoopbackState;
+/// use embassy_net::udp::{NewSocketError, UdpBufferPool, UdpSocket};
+/// use embassy_net::{Config, StackResources};
+///
+/// let state = LoopbackState::<1500, 4>::new();
+/// let mut resources = StackResources::<4>::new();
+/// let (stack, _runner) = embassy_net::new(state.device(), Config::default(), &mut resources, 1);
+///
+/// let pool = UdpBufferPool::<1, 4, 1024, 4, 1024>::new();
+/// let socket = UdpSocket::new_pooled(stack, &pool).unwrap();
+/// assert_eq!(UdpSocket::new_pooled(stack, &pool).err(), Some(NewSocketError::PoolExhausted));
+///
+/// // Dropping the socket returns its buffers to the pool.
+/// drop(socket);
+/// let mut socket = UdpSocket::new_pooled(stack, &pool).unwrap();
+/// socket.bind(1234).unwrap();
+/// # }
+/// ```
+pub struct UdpBufferPool<
+    const N: usize,
+    const RX_META: usize,
+    const RX_SZ: usize,
+    const TX_META: usize,
+    const TX_SZ: usize,
+> {
+    pool: Pool<PoolBuffers<RX_META, RX_SZ, TX_META, TX_SZ>, N>,
+}
+
+impl<const N: usize, const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize>
+    UdpBufferPool<N, RX_META, RX_SZ, TX_META, TX_SZ>
+{
+    /// Create a new pool.
+    pub const fn new() -> Self {
+        Self { pool: Pool::new() }
+    }
+}
+
+impl<const N: usize, const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize> Default
+    for UdpBufferPool<N, RX_META, RX_SZ, TX_META, TX_SZ>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// UDP socket using buffers borrowed from a [`UdpBufferPool`].
+///
+/// Created by [`UdpSocket::new_pooled`], and returns the buffers to the pool when dropped.
+///
+/// It dereferences to the underlying [`UdpSocket`] for the methods taking `&self`. The methods
+/// taking `&mut self` are forwarded one by one, since handing out `&mut UdpSocket` would allow
+/// moving the socket out while the pool lends its buffers to another one.
+///
+/// ```compile_fail
+/// # use embassy_net::udp::{PooledUdpSocket, UdpSocket};
+/// fn swap(a: &mut PooledUdpSocket<'_, 2, 4, 512, 4, 512>, b: &mut UdpSocket<'_>) {
+///     core::mem::swap(&mut **a, b);
+/// }
+/// ```
+pub struct PooledUdpSocket<
+    'd,
+    const N: usize,
+    const RX_META: usize,
+    const RX_SZ: usize,
+    const TX_META: usize,
+    const TX_SZ: usize,
+> {
+    socket: ManuallyDrop<UdpSocket<'d>>,
+    pool: &'d UdpBufferPool<N, RX_META, RX_SZ, TX_META, TX_SZ>,
+    bufs: NonNull<PoolBuffers<RX_META, RX_SZ, TX_META, TX_SZ>>,
+}
+
+impl<'d, const N: usize, const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize> Deref
+    for PooledUdpSocket<'d, N, RX_META, RX_SZ, TX_META, TX_SZ>
+{
+    type Target = UdpSocket<'d>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.socket
+    }
+}
+
+impl<'d, const N: usize, const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize>
+    PooledUdpSocket<'d, N, RX_META, RX_SZ, TX_META, TX_SZ>
+{
+    /// Bind the socket to a local endpoint.
+    ///
+    /// See [`UdpSocket::bind`].
+    pub fn bind<T>(&mut self, endpoint: T) -> Result<(), BindError>
+    where
+        T: Into<IpListenEndpoint>,
+    {
+        self.socket.bind(endpoint)
+    }
+
+    /// Bind the socket to a new local endpoint.
+    ///
+    /// See [`UdpSocket::rebind`].
+    pub fn rebind<T>(&mut self, endpoint: T) -> Result<(), BindError>
+    where
+        T: Into<IpListenEndpoint>,
+    {
+        self.socket.rebind(endpoint)
+    }
+
+    /// Connect the socket to a remote endpoint.
+    ///
+    /// See [`UdpSocket::connect`].
+    pub fn connect<T>(&mut self, remote: T)
+    where
+        T: Into<IpEndpoint>,
+    {
+        self.socket.connect(remote)
+    }
+
+    /// Disconnect the socket from its remote endpoint.
+    ///
+    /// See [`UdpSocket::disconnect`].
+    pub fn disconnect(&mut self) {
+        self.socket.disconnect()
+    }
+
+    /// Set the filter applied to the source of received datagrams.
+    ///
+    /// See [`UdpSocket::set_recv_filter`].
+    pub fn set_recv_filter(&mut self, filter: Option<fn(IpEndpoint) -> bool>) {
+        self.socket.set_recv_filter(filter)
+    }
+
+    /// Receive a datagram with a zero-copy function.
+    ///
+    /// See [`UdpSocket::recv_from_with`].
     pub async fn recv_from_with<F, R>(&mut self, f: F) -> R
     where
         F: FnOnce(&[u8], UdpMetadata) -> R,
     {
-        let mut f = Some(f);
-        poll_fn(move |cx| {
-            self.with_mut(|s, _| {
-                match s.recv() {
-                    Ok((buffer, endpoint)) => Poll::Ready(unwrap!(f.take())(buffer, endpoint)),
-                    Err(udp::RecvError::Truncated) => unreachable!(),
-                    Err(udp::RecvError::Exhausted) => {
-                        // socket buffer is empty wait until at least one byte has arrived
-                        s.register_recv_waker(cx.waker());
-                        Poll::Pending
-                    }
-                }
-            })
-        })
-        .await
+        self.socket.recv_from_with(f).await
     }
 
-    /// Wait until the socket becomes writable.
+    /// Receive a datagram with a zero-copy function, failing if the stack was stopped.
     ///
-    /// A socket becomes writable when there is space in the buffer, from initial memory or after
-    /// dispatching datagrams on a full buffer.
-    pub fn wait_send_ready(&self) -> impl Future<Output = ()> + '_ {
-        poll_fn(|cx| self.poll_send_ready(cx))
+    /// See [`UdpSocket::try_recv_from_with`].
+    pub async fn try_recv_from_with<F, R>(&self, f: F) -> Result<R, RecvError>
+    where
+        F: FnOnce(&[u8], UdpMetadata) -> R,
+    {
+        self.socket.try_recv_from_with(f).await
     }
 
-    /// Wait until a datagram can be sent.
-    ///
-    /// When no datagram can be sent (i.e. the buffer is full), this method will return
-    /// `Poll::Pending` and register the current task to be notified when
-    /// space is freed in the buffer after a datagram has been dispatched.
+    /// Send a datagram to the specified remote endpoint with a zero-copy function.
     ///
-    /// When a datagram can be sent, this method will return `Poll::Ready`.
-    pub fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
-        self.with_mut(|s, _| {
-            if s.can_send() {
-                Poll::Ready(())
-            } else {
-                // socket buffer is full wait until a datagram has been dispatched
-                s.register_send_waker(cx.waker());
-                Poll::Pending
-            }
-        })
+    /// See [`UdpSocket::send_to_with`].
+    pub async fn send_to_with<T, F, R>(&mut self, size: usize, remote_endpoint: T, f: F) -> Result<R, SendError>
+    where
+        T: Into<UdpMetadata> + Copy,
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.socket.send_to_with(size, remote_endpoint, f).await
     }
 
-    /// Send a datagram to the specified remote endpoint.
+    /// Flush the socket.
     ///
-    /// This method will wait until the datagram has been sent.
+    /// See [`UdpSocket::flush`].
+    pub fn flush(&mut self) -> impl Future<Output = ()> + '_ {
+        self.socket.flush()
+    }
+
+    /// Close the socket.
     ///
-    /// If the socket's send buffer is too small to fit `buf`, this method will return `Err(SendError::PacketTooLarge)`
+    /// See [`UdpSocket::close`].
+    pub fn close(&mut self) {
+        self.socket.close()
+    }
+
+    /// Close the socket once all queued datagrams have been sent.
     ///
-    /// When the remote endpoint is not reachable, this method will return `Err(SendError::NoRoute)`
-    pub async fn send_to<T>(&self, buf: &[u8], remote_endpoint: T) -> Result<(), SendError>
-    where
-        T: Into<UdpMetadata>,
-    {
-        let remote_endpoint: UdpMetadata = remote_endpoint.into();
-        poll_fn(move |cx| self.poll_send_to(buf, remote_endpoint, cx)).await
+    /// See [`UdpSocket::close_graceful`].
+    pub async fn close_graceful(&mut self) {
+        self.socket.close_graceful().await
     }
 
-    /// Send a datagram to the specified remote endpoint.
+    /// Set the hop limit of sent datagrams.
     ///
-    /// When the datagram has been sent, this method will return `Poll::Ready(Ok())`.
+    /// See [`UdpSocket::set_hop_limit`].
+    pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
+        self.socket.set_hop_limit(hop_limit)
+    }
+
+    /// Join a multicast group.
     ///
-    /// When the socket's send buffer is full, this method will return `Poll::Pending`
-    /// and register the current task to be notified when the buffer has space available.
+    /// See [`UdpSocket::join_multicast_group`].
+    #[cfg(feature = "multicast")]
+    pub fn join_multicast_group(&mut self, addr: impl Into<IpAddress>) -> Result<bool, MulticastError> {
+        self.socket.join_multicast_group(addr)
+    }
+
+    /// Leave a multicast group.
     ///
-    /// If the socket's send buffer is too small to fit `buf`, this method will return `Poll::Ready(Err(SendError::PacketTooLarge))`
+    /// See [`UdpSocket::leave_multicast_group`].
+    #[cfg(feature = "multicast")]
+    pub fn leave_multicast_group(&mut self, addr: impl Into<IpAddress>) -> Result<bool, MulticastError> {
+        self.socket.leave_multicast_group(addr)
+    }
+
+    /// Split the socket into receive and transmit halves.
     ///
-    /// When the remote endpoint is not reachable, this method will return `Poll::Ready(Err(Error::NoRoute))`.
-    pub fn poll_send_to<T>(&self, buf: &[u8], remote_endpoint: T, cx: &mut Context<'_>) -> Poll<Result<(), SendError>>
-    where
-        T: Into<UdpMetadata>,
-    {
-        // Don't need to wake waker in `with_mut` if the buffer will never fit the udp tx_buffer.
-        let send_capacity_too_small = self.with(|s, _| s.payload_send_capacity() < buf.len());
-        if send_capacity_too_small {
-            return Poll::Ready(Err(SendError::PacketTooLarge));
+    /// See [`UdpSocket::split`].
+    pub fn split(&mut self) -> (UdpRx<'_>, UdpTx<'_>) {
+        self.socket.split()
+    }
+}
+
+impl<const N: usize, const RX_META: usize, const RX_SZ: usize, const TX_META: usize, const TX_SZ: usize> Drop
+    for PooledUdpSocket<'_, N, RX_META, RX_SZ, TX_META, TX_SZ>
+{
+    fn drop(&mut self) {
+        // Remove the socket from the stack before its buffers can be lent to another one.
+        unsafe {
+            ManuallyDrop::drop(&mut self.socket);
+            self.pool.pool.free(self.bufs);
         }
+    }
+}
 
-        self.with_mut(|s, _| match s.send_slice(buf, remote_endpoint) {
-            // Entire datagram has been sent
-            Ok(()) => Poll::Ready(Ok(())),
-            Err(udp::SendError::BufferFull) => {
-                s.register_send_waker(cx.waker());
-                Poll::Pending
+/// UDP client compatible with `embedded-nal-async` traits.
+pub mod client {
+    use core::net::{IpAddr, SocketAddr};
+
+    use super::*;
+
+    /// Error returned by the `embedded-nal-async` UDP client.
+    #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum Error {
+        /// All sockets of the [`UdpClientState`] or of the stack are in use.
+        NoFreeSocket,
+        /// Binding the socket failed.
+        Bind(BindError),
+        /// Sending a datagram failed.
+        Send(SendError),
+        /// The stack was stopped while receiving a datagram.
+        Stopped,
+    }
+
+    impl core::fmt::Display for Error {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                Self::NoFreeSocket => write!(f, "No Free Socket"),
+                Self::Bind(e) => write!(f, "Bind Failed: {e}"),
+                Self::Send(e) => write!(f, "Send Failed: {e}"),
+                Self::Stopped => write!(f, "Stack Stopped"),
             }
-            Err(udp::SendError::Unaddressable) => {
-                // If no sender/outgoing port is specified, there is not really "no route"
-                if s.endpoint().port == 0 {
-                    Poll::Ready(Err(SendError::SocketNotBound))
-                } else {
-                    Poll::Ready(Err(SendError::NoRoute))
-                }
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    impl From<BindError> for Error {
+        fn from(e: BindError) -> Self {
+            Error::Bind(e)
+        }
+    }
+
+    impl From<SendError> for Error {
+        fn from(e: SendError) -> Self {
+            Error::Send(e)
+        }
+    }
+
+    impl embedded_io_async::Error for Error {
+        fn kind(&self) -> embedded_io_async::ErrorKind {
+            match self {
+                Error::NoFreeSocket => embedded_io_async::ErrorKind::OutOfMemory,
+                Error::Bind(BindError::InvalidState) => embedded_io_async::ErrorKind::Other,
+                Error::Bind(BindError::NoRoute) => embedded_io_async::ErrorKind::AddrNotAvailable,
+                Error::Bind(BindError::NoFreePort) => embedded_io_async::ErrorKind::AddrInUse,
+                Error::Send(SendError::NoRoute) => embedded_io_async::ErrorKind::AddrNotAvailable,
+                Error::Send(SendError::SocketNotBound) => embedded_io_async::ErrorKind::NotConnected,
+                Error::Send(SendError::NotConnected) => embedded_io_async::ErrorKind::NotConnected,
+                Error::Send(SendError::PacketTooLarge) => embedded_io_async::ErrorKind::InvalidInput,
+                Error::Send(SendError::Stopped) | Error::Stopped => embedded_io_async::ErrorKind::ConnectionAborted,
             }
-        })
+        }
     }
 
-    /// Send a datagram to the specified remote endpoint with a zero-copy function.
+    /// UDP socket pool compatible with `embedded-nal-async` traits.
     ///
-    /// This method will wait until the buffer can fit the requested size before
-    /// calling the function to fill its contents.
+    /// The pool is capable of managing up to N concurrent sockets with tx and rx buffers according to TX_SZ and RX_SZ,
+    /// each able to queue up to META datagrams per direction.
     ///
-    /// If the socket's send buffer is too small to fit `size`, this method will return `Err(SendError::PacketTooLarge)`
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use core::net::{Ipv4Addr, SocketAddr};
     ///
-    /// When the remote endpoint is not reachable, this method will return `Err(SendError::NoRoute)`
-    pub async fn send_to_with<T, F, R>(&mut self, size: usize, remote_endpoint: T, f: F) -> Result<R, SendError>
-    where
-        T: Into<UdpMetadata> + Copy,
-        F: FnOnce(&mut [u8]) -> R,
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::client::{UdpClient, UdpClientState};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use embedded_nal_async::{ConnectedUdp, UdpStack, UnconnectedUdp};
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let client_state = UdpClientState::<1, 256, 256, 4>::new();
+    /// let client = UdpClient::new(stack_a, &client_state);
+    /// let server_state = UdpClientState::<1, 256, 256, 4>::new();
+    /// let server = UdpClient::new(stack_b, &server_state);
+    ///
+    /// let test = async {
+    ///     let server_addr = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 5678);
+    ///     let mut server_socket = server
+    ///         .bind_multiple(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 5678))
+    ///         .await
+    ///         .unwrap();
+    ///     let (local, mut client_socket) = client.connect(server_addr).await.unwrap();
+    ///     assert_eq!(local.ip(), Ipv4Addr::new(10, 0, 0, 1));
+    ///
+    ///     ConnectedUdp::send(&mut client_socket, b"ping").await.unwrap();
+    ///     let mut buf = [0; 16];
+    ///     let (n, to, from) = UnconnectedUdp::receive_into(&mut server_socket, &mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..n], b"ping");
+    ///     assert_eq!((to, from), (server_addr, local));
+    ///
+    ///     UnconnectedUdp::send(&mut server_socket, to, from, b"pong").await.unwrap();
+    ///     let n = ConnectedUdp::receive_into(&mut client_socket, &mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..n], b"pong");
+    /// };
+    /// match block_on(select3(test, runner_a.run(), runner_b.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub struct UdpClient<
+        'd,
+        const N: usize,
+        const TX_SZ: usize = 1024,
+        const RX_SZ: usize = 1024,
+        const META: usize = 4,
+    > {
+        stack: Stack<'d>,
+        state: &'d UdpClientState<N, TX_SZ, RX_SZ, META>,
+    }
+
+    impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const META: usize>
+        UdpClient<'d, N, TX_SZ, RX_SZ, META>
     {
-        // Don't need to wake waker in `with_mut` if the buffer will never fit the udp tx_buffer.
-        let send_capacity_too_small = self.with(|s, _| s.payload_send_capacity() < size);
-        if send_capacity_too_small {
-            return Err(SendError::PacketTooLarge);
+        /// Create a new `UdpClient`.
+        pub fn new(stack: Stack<'d>, state: &'d UdpClientState<N, TX_SZ, RX_SZ, META>) -> Self {
+            Self { stack, state }
         }
 
-        let mut f = Some(f);
-        poll_fn(move |cx| {
-            self.with_mut(|s, _| {
-                match s.send(size, remote_endpoint) {
-                    Ok(buffer) => Poll::Ready(Ok(unwrap!(f.take())(buffer))),
-                    Err(udp::SendError::BufferFull) => {
-                        s.register_send_waker(cx.waker());
-                        Poll::Pending
-                    }
-                    Err(udp::SendError::Unaddressable) => {
-                        // If no sender/outgoing port is specified, there is not really "no route"
-                        if s.endpoint().port == 0 {
-                            Poll::Ready(Err(SendError::SocketNotBound))
-                        } else {
-                            Poll::Ready(Err(SendError::NoRoute))
-                        }
-                    }
-                }
-            })
-        })
-        .await
+        fn bind(&self, local: SocketAddr) -> Result<UdpClientSocket<'d, N, TX_SZ, RX_SZ, META>, Error> {
+            let mut socket = UdpClientSocket::new(self.stack, self.state)?;
+            let addr = (!local.ip().is_unspecified()).then(|| to_ip_address(local.ip()));
+            socket.socket.bind(IpListenEndpoint {
+                addr,
+                port: local.port(),
+            })?;
+            Ok(socket)
+        }
     }
 
-    /// Flush the socket.
-    ///
-    /// This method will wait until the socket is flushed.
-    pub fn flush(&mut self) -> impl Future<Output = ()> + '_ {
-        poll_fn(|cx| {
-            self.with_mut(|s, _| {
-                if s.send_queue() == 0 {
-                    Poll::Ready(())
-                } else {
-                    s.register_send_waker(cx.waker());
-                    Poll::Pending
-                }
-            })
-        })
-    }
+    impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const META: usize> embedded_nal_async::UdpStack
+        for UdpClient<'d, N, TX_SZ, RX_SZ, META>
+    {
+        type Error = Error;
+        type Connected = UdpClientSocket<'d, N, TX_SZ, RX_SZ, META>;
+        type UniquelyBound = UdpClientSocket<'d, N, TX_SZ, RX_SZ, META>;
+        type MultiplyBound = UdpClientSocket<'d, N, TX_SZ, RX_SZ, META>;
 
-    /// Returns the local endpoint of the socket.
-    pub fn endpoint(&self) -> IpListenEndpoint {
-        self.with(|s, _| s.endpoint())
-    }
+        async fn connect_from(
+            &self,
+            local: SocketAddr,
+            remote: SocketAddr,
+        ) -> Result<(SocketAddr, Self::Connected), Self::Error> {
+            let mut socket = self.bind(local)?;
+            socket
+                .socket
+                .connect(IpEndpoint::new(to_ip_address(remote.ip()), remote.port()));
+            Ok((socket.local_addr(local), socket))
+        }
 
-    /// Returns whether the socket is open.
+        async fn bind_single(&self, local: SocketAddr) -> Result<(SocketAddr, Self::UniquelyBound), Self::Error> {
+            let socket = self.bind(local)?;
+            Ok((socket.local_addr(local), socket))
+        }
 
-    pub fn is_open(&self) -> bool {
-        self.with(|s, _| s.is_open())
+        async fn bind_multiple(&self, local: SocketAddr) -> Result<Self::MultiplyBound, Self::Error> {
+            self.bind(local)
+        }
     }
 
-    /// Close the socket.
-    pub fn close(&mut self) {
-        self.with_mut(|s, _| s.close())
+    /// UDP socket allocated by a [`UdpClient`].
+    pub struct UdpClientSocket<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const META: usize> {
+        socket: PooledUdpSocket<'d, N, META, RX_SZ, META, TX_SZ>,
     }
 
-    /// Returns whether the socket is ready to send data, i.e. it has enough buffer space to hold a packet.
-    pub fn may_send(&self) -> bool {
-        self.with(|s, _| s.can_send())
+    impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const META: usize>
+        UdpClientSocket<'d, N, TX_SZ, RX_SZ, META>
+    {
+        fn new(stack: Stack<'d>, state: &'d UdpClientState<N, TX_SZ, RX_SZ, META>) -> Result<Self, Error> {
+            match UdpSocket::new_pooled(stack, &state.pool) {
+                Ok(socket) => Ok(Self { socket }),
+                Err(NewSocketError::NoFreeSocket | NewSocketError::PoolExhausted) => Err(Error::NoFreeSocket),
+                Err(NewSocketError::EmptyBuffer) => panic!("UdpClientState buffer sizes must not be zero"),
+            }
+        }
+
+        /// Local address as reported to `embedded-nal-async`, falling back to the
+        /// requested one while the stack has no address yet.
+        fn local_addr(&self, requested: SocketAddr) -> SocketAddr {
+            match self.socket.local_endpoint() {
+                Some(ep) => SocketAddr::new(ep.addr.into(), ep.port),
+                None => SocketAddr::new(requested.ip(), self.socket.endpoint().port),
+            }
+        }
+
+        /// Receive a datagram, truncating it to `buffer` and returning its full length.
+        async fn recv_truncating(&mut self, buffer: &mut [u8]) -> Result<(usize, UdpMetadata), Error> {
+            self.socket
+                .try_recv_from_with(|data, meta| {
+                    let n = data.len().min(buffer.len());
+                    buffer[..n].copy_from_slice(&data[..n]);
+                    (data.len(), meta)
+                })
+                .await
+                .map_err(|_| Error::Stopped)
+        }
     }
 
-    /// Returns whether the socket is ready to receive data, i.e. it has received a packet that's now in the buffer.
-    pub fn may_recv(&self) -> bool {
-        self.with(|s, _| s.can_recv())
+    impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const META: usize> embedded_nal_async::ConnectedUdp
+        for UdpClientSocket<'d, N, TX_SZ, RX_SZ, META>
+    {
+        type Error = Error;
+
+        async fn send(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            Ok(self.socket.send(data).await?)
+        }
+
+        async fn receive_into(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+            loop {
+                let (n, meta) = self.recv_truncating(buffer).await?;
+                // Drop datagrams that don't come from the connected peer.
+                if self.socket.remote_endpoint().is_none_or(|r| r == meta.endpoint) {
+                    return Ok(n);
+                }
+            }
+        }
     }
 
-    /// Return the maximum number packets the socket can receive.
-    pub fn packet_recv_capacity(&self) -> usize {
-        self.with(|s, _| s.packet_recv_capacity())
+    impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const META: usize>
+        embedded_nal_async::UnconnectedUdp for UdpClientSocket<'d, N, TX_SZ, RX_SZ, META>
+    {
+        type Error = Error;
+
+        async fn send(&mut self, local: SocketAddr, remote: SocketAddr, data: &[u8]) -> Result<(), Self::Error> {
+            let mut meta: UdpMetadata = IpEndpoint::new(to_ip_address(remote.ip()), remote.port()).into();
+            if !local.ip().is_unspecified() {
+                meta.local_address = Some(to_ip_address(local.ip()));
+            }
+            Ok(self.socket.send_to(data, meta).await?)
+        }
+
+        async fn receive_into(&mut self, buffer: &mut [u8]) -> Result<(usize, SocketAddr, SocketAddr), Self::Error> {
+            let (n, meta) = self.recv_truncating(buffer).await?;
+            // Received datagrams always carry the address they were sent to.
+            let local = SocketAddr::new(unwrap!(meta.local_address).into(), self.socket.endpoint().port);
+            let remote = SocketAddr::new(meta.endpoint.addr.into(), meta.endpoint.port);
+            Ok((n, local, remote))
+        }
     }
 
-    /// Return the maximum number packets the socket can receive.
-    pub fn packet_send_capacity(&self) -> usize {
-        self.with(|s, _| s.packet_send_capacity())
+    /// State for UdpClient
+    pub struct UdpClientState<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const META: usize> {
+        pool: UdpBufferPool<N, META, RX_SZ, META, TX_SZ>,
     }
 
-    /// Return the maximum number of bytes inside the recv buffer.
-    pub fn payload_recv_capacity(&self) -> usize {
-        self.with(|s, _| s.payload_recv_capacity())
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const META: usize> UdpClientState<N, TX_SZ, RX_SZ, META> {
+        /// Create a new `UdpClientState`.
+        pub const fn new() -> Self {
+            Self {
+                pool: UdpBufferPool::new(),
+            }
+        }
     }
 
-    /// Return the maximum number of bytes inside the transmit buffer.
-    pub fn payload_send_capacity(&self) -> usize {
-        self.with(|s, _| s.payload_send_capacity())
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const META: usize> Default
+        for UdpClientState<N, TX_SZ, RX_SZ, META>
+    {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
-    /// Set the hop limit field in the IP header of sent packets.
-    pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
-        self.with_mut(|s, _| s.set_hop_limit(hop_limit))
+    fn to_ip_address(addr: IpAddr) -> IpAddress {
+        match addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddr::V4(addr) => IpAddress::Ipv4(addr),
+            #[cfg(not(feature = "proto-ipv4"))]
+            IpAddr::V4(_) => panic!("ipv4 support not enabled"),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddr::V6(addr) => IpAddress::Ipv6(addr),
+            #[cfg(not(feature = "proto-ipv6"))]
+            IpAddr::V6(_) => panic!("ipv6 support not enabled"),
+        }
     }
 }
 
-impl Drop for UdpSocket<'_> {
-    fn drop(&mut self) {
-        self.stack.with_mut(|i| i.sockets.remove(self.handle));
+/// Fan-out of datagrams received on one socket to several channels.
+pub mod dispatch {
+    use embassy_sync::blocking_mutex::raw::RawMutex;
+    use embassy_sync::channel::Channel;
+    use heapless::Vec;
+
+    use super::*;
+
+    /// A received datagram, as delivered by [`UdpDispatcher`].
+    #[derive(Debug, Clone)]
+    pub struct Datagram<const SZ: usize> {
+        /// Datagram payload.
+        pub data: Vec<u8, SZ>,
+        /// Datagram metadata, including the remote endpoint.
+        pub meta: UdpMetadata,
     }
-}
 
-fn _assert_covariant<'a, 'b: 'a>(x: UdpSocket<'b>) -> UdpSocket<'a> {
-    x
+    /// Routes datagrams received on a [`UdpSocket`] to channels.
+    ///
+    /// For each received datagram, the classifier returns the index of the channel in
+    /// `channels` to deliver it to, or `None` to discard it. Datagrams with an out-of-range
+    /// index and datagrams larger than `SZ` bytes are discarded as well.
+    ///
+    /// When the target channel is full, [`run`](Self::run) waits for it to have space before
+    /// receiving the next datagram, so a slow consumer backpressures the socket.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+    /// # {
+    /// use embassy_futures::block_on;
+    /// use embassy_futures::select::{select, select3, Either3};
+    /// use embassy_net::loopback::LoopbackState;
+    /// use embassy_net::udp::dispatch::{Datagram, UdpDispatcher};
+    /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+    /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    /// use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    /// use embassy_sync::channel::Channel;
+    ///
+    /// fn config(addr: Ipv4Address) -> Config {
+    ///     Config::ipv4_static(StaticConfigV4 {
+    ///         address: Ipv4Cidr::new(addr, 24),
+    ///         gateway: None,
+    ///         dns_servers: Default::default(),
+    ///     })
+    /// }
+    ///
+    /// let state = LoopbackState::<1500, 4>::new();
+    /// let (dev_a, dev_b) = state.pair();
+    /// let mut resources_a = StackResources::<2>::new();
+    /// let mut resources_b = StackResources::<2>::new();
+    /// let (stack_a, mut runner_a) = embassy_net::new(dev_a, config(Ipv4Address::new(10, 0, 0, 1)), &mut resources_a, 1);
+    /// let (stack_b, mut runner_b) = embassy_net::new(dev_b, config(Ipv4Address::new(10, 0, 0, 2)), &mut resources_b, 2);
+    ///
+    /// let mut rx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_a = [0; 256];
+    /// let mut tx_meta_a = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_a = [0; 256];
+    /// let mut a = UdpSocket::new(stack_a, &mut rx_meta_a, &mut rx_a, &mut tx_meta_a, &mut tx_a);
+    /// a.bind(1234).unwrap();
+    ///
+    /// let mut rx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut rx_b = [0; 256];
+    /// let mut tx_meta_b = [PacketMetadata::EMPTY; 4];
+    /// let mut tx_b = [0; 256];
+    /// let mut b = UdpSocket::new(stack_b, &mut rx_meta_b, &mut rx_b, &mut tx_meta_b, &mut tx_b);
+    /// b.bind(5678).unwrap();
+    ///
+    /// // Route datagrams by their first byte, discarding unknown ones.
+    /// let control = Channel::<NoopRawMutex, Datagram<16>, 4>::new();
+    /// let data = Channel::<NoopRawMutex, Datagram<16>, 4>::new();
+    /// let channels = [&control, &data];
+    /// let mut dispatcher = UdpDispatcher::new(b, &channels, |payload, _| match payload.first() {
+    ///     Some(b'c') => Some(0),
+    ///     Some(b'd') => Some(1),
+    ///     _ => None,
+    /// });
+    ///
+    /// let test = async {
+    ///     let remote = (Ipv4Address::new(10, 0, 0, 2), 5678);
+    ///     for payload in [&b"c1"[..], b"d1", b"x", b"d2", b"c2"] {
+    ///         a.send_to(payload, remote).await.unwrap();
+    ///     }
+    ///     assert_eq!(control.receive().await.data, b"c1");
+    ///     assert_eq!(control.receive().await.data, b"c2");
+    ///     assert_eq!(data.receive().await.data, b"d1");
+    ///     let d2 = data.receive().await;
+    ///     assert_eq!(d2.data, b"d2");
+    ///     assert_eq!(d2.meta.endpoint, (Ipv4Address::new(10, 0, 0, 1), 1234).into());
+    ///     assert!(control.is_empty() && data.is_empty());
+    /// };
+    /// match block_on(select3(test, select(runner_a.run(), runner_b.run()), dispatcher.run())) {
+    ///     Either3::First(()) => {}
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub struct UdpDispatcher<'a, 'c, M: RawMutex, F, const SZ: usize, const N: usize> {
+        socket: UdpSocket<'a>,
+        channels: &'c [&'c Channel<M, Datagram<SZ>, N>],
+        classify: F,
+    }
+
+    impl<'a, 'c, M, F, const SZ: usize, const N: usize> UdpDispatcher<'a, 'c, M, F, SZ, N>
+    where
+        M: RawMutex,
+        F: FnMut(&[u8], &UdpMetadata) -> Option<usize>,
+    {
+        /// Create a new dispatcher for a bound socket.
+        pub fn new(socket: UdpSocket<'a>, channels: &'c [&'c Channel<M, Datagram<SZ>, N>], classify: F) -> Self {
+            Self {
+                socket,
+                channels,
+                classify,
+            }
+        }
+
+        /// Receive and dispatch datagrams.
+        ///
+        /// This should be run in a dedicated task. It returns once the stack was stopped, see
+        /// [`Runner::run_until_stopped`](crate::Runner::run_until_stopped).
+        ///
+        /// ```
+        /// # #[cfg(all(feature = "test-util", feature = "proto-ipv4"))]
+        /// # {
+        /// use embassy_futures::block_on;
+        /// use embassy_futures::join::join3;
+        /// use embassy_net::loopback::LoopbackState;
+        /// use embassy_net::udp::dispatch::{Datagram, UdpDispatcher};
+        /// use embassy_net::udp::{PacketMetadata, UdpSocket};
+        /// use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+        /// use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+        /// use embassy_sync::channel::Channel;
+        /// use embassy_time::Duration;
+        ///
+        /// let config = Config::ipv4_static(StaticConfigV4 {
+        ///     address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+        ///     gateway: None,
+        ///     dns_servers: Default::default(),
+        /// });
+        /// let state = LoopbackState::<1500, 4>::new();
+        /// let mut resources = StackResources::<2>::new();
+        /// let (stack, mut runner) = embassy_net::new(state.device(), config, &mut resources, 1);
+        ///
+        /// let mut rx_meta = [PacketMetadata::EMPTY; 4];
+        /// let mut rx = [0; 256];
+        /// let mut tx_meta = [PacketMetadata::EMPTY; 4];
+        /// let mut tx = [0; 256];
+        /// let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+        /// socket.bind(1234).unwrap();
+        ///
+        /// let channel = Channel::<NoopRawMutex, Datagram<16>, 4>::new();
+        /// let channels = [&channel];
+        /// let mut dispatcher = UdpDispatcher::new(socket, &channels, |_, _| Some(0));
+        ///
+        /// // Nothing is ever received, the dispatcher ends when the stack stops.
+        /// block_on(join3(
+        ///     runner.run_until_stopped(Duration::from_millis(50)),
+        ///     dispatcher.run(),
+        ///     async { stack.request_stop() },
+        /// ));
+        /// assert!(channel.is_empty());
+        /// # }
+        /// ```
+        pub async fn run(&mut self) {
+            loop {
+                let classify = &mut self.classify;
+                let channels = self.channels;
+                let routed = self
+                    .socket
+                    .try_recv_from_with(|data, meta| {
+                        let index = classify(data, &meta).filter(|&i| i < channels.len())?;
+                        let data = Vec::from_slice(data).ok()?;
+                        Some((index, Datagram { data, meta }))
+                    })
+                    .await;
+                let Ok(routed) = routed else {
+                    return;
+                };
+
+                if let Some((index, datagram)) = routed {
+                    channels[index].send(datagram).await;
+                }
+            }
+        }
+
+        /// Return the socket, consuming the dispatcher.
+        pub fn into_socket(self) -> UdpSocket<'a> {
+            self.socket
+        }
+    }
 }